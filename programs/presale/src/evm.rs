@@ -0,0 +1,113 @@
+// Parsing and formatting of the EVM addresses submitted by users.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::keccak;
+
+use crate::ErrorCode;
+
+// The length in bytes of an EVM address.
+pub const EVM_ADDRESS_LEN: usize = 20;
+
+// Parses a 0x-prefixed EVM address of exactly 40 hex digits.
+// If the digits are mixed case, the EIP-55 checksum must also match.
+pub fn parse_evm_address(input: &str) -> Result<[u8; EVM_ADDRESS_LEN]> {
+    let digits = input.strip_prefix("0x").ok_or(ErrorCode::InvalidEvmAddress)?.as_bytes();
+    require!(digits.len() == EVM_ADDRESS_LEN * 2, ErrorCode::InvalidEvmAddress);
+
+    // Decode the hex digits into raw bytes.
+    let mut address = [0u8; EVM_ADDRESS_LEN];
+    for (byte, pair) in address.iter_mut().zip(digits.chunks(2)) {
+        *byte = (hex_value(pair[0])? << 4) | hex_value(pair[1])?;
+    }
+
+    // Verify the checksum only when the address is mixed case, as all lower or upper case addresses carry none.
+    let has_lower = digits.iter().any(|c| c.is_ascii_lowercase());
+    let has_upper = digits.iter().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper {
+        require!(
+            digits == checksum_digits(&address).as_bytes(),
+            ErrorCode::InvalidEvmAddressChecksum
+        );
+    }
+
+    Ok(address)
+}
+
+// Formats an EVM address as a 0x-prefixed EIP-55 checksummed string.
+pub fn format_evm_address(address: &[u8; EVM_ADDRESS_LEN]) -> String {
+    format!("0x{}", checksum_digits(address))
+}
+
+// Returns the 40 hex digits of an address with the EIP-55 checksum casing applied.
+fn checksum_digits(address: &[u8; EVM_ADDRESS_LEN]) -> String {
+    let lower: String = address
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let hash = keccak::hash(lower.as_bytes()).to_bytes();
+
+    // Upper case each letter whose corresponding nibble of the hash is 8 or more.
+    lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+            if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect()
+}
+
+// Returns the value of a single hex digit.
+fn hex_value(digit: u8) -> Result<u8> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => err!(ErrorCode::InvalidEvmAddress),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checksummed address from the EIP-55 test vectors.
+    const CHECKSUMMED: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    #[test]
+    fn accepts_a_valid_checksum() {
+        let address = parse_evm_address(CHECKSUMMED).unwrap();
+        assert_eq!(format_evm_address(&address), CHECKSUMMED);
+    }
+
+    #[test]
+    fn accepts_single_case_addresses_without_a_checksum() {
+        let expected = parse_evm_address(CHECKSUMMED).unwrap();
+        let lower = format!("0x{}", CHECKSUMMED[2..].to_ascii_lowercase());
+        let upper = format!("0x{}", CHECKSUMMED[2..].to_ascii_uppercase());
+        assert_eq!(parse_evm_address(&lower).unwrap(), expected);
+        assert_eq!(parse_evm_address(&upper).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_an_invalid_mixed_case_checksum() {
+        // Same address with the case of the first letter flipped.
+        let invalid = "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        assert_eq!(
+            parse_evm_address(invalid).unwrap_err(),
+            ErrorCode::InvalidEvmAddressChecksum.into()
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for input in [
+            "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAe",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAedd",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeg",
+        ] {
+            assert_eq!(parse_evm_address(input).unwrap_err(), ErrorCode::InvalidEvmAddress.into());
+        }
+    }
+}
@@ -7,12 +7,83 @@
 
 // Import necessary modules from the Anchor framework and the standard library.
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::{ program::invoke, system_instruction };
+use anchor_lang::Discriminator;
+use anchor_lang::solana_program::{
+    hash::hashv,
+    native_token::LAMPORTS_PER_SOL,
+    program::invoke,
+    system_instruction,
+    sysvar::instructions::{ self as instructions_sysvar, load_current_index_checked, load_instruction_at_checked },
+};
 use solana_security_txt::security_txt;
 
+pub mod evm;
+pub mod math;
+
 // Declare the unique identifier for this Solana program.
 declare_id!("AquaFurRSVeVin1wJPmf7bvP6fCEKBqQbdpq6fr3aPy5");
 
+// Byte offsets of the presale and buyer keys in BuyerHistory and PurchaseReceipt accounts,
+// right after the 8 byte discriminator, for getProgramAccounts memcmp filters.
+#[constant]
+pub const RECEIPT_PRESALE_OFFSET: usize = 8;
+#[constant]
+pub const RECEIPT_BUYER_OFFSET: usize = 40;
+
+// The maximum number of destinations in the withdrawal allowlist.
+#[constant]
+pub const MAX_WITHDRAWAL_DESTINATIONS: usize = 4;
+
+// The maximum length in bytes of a purchase memo.
+#[constant]
+pub const MAX_MEMO_LEN: usize = 128;
+
+// The number of entries kept in the audit log ring buffer.
+#[constant]
+pub const AUDIT_LOG_CAPACITY: usize = 32;
+
+// The number of previous EVM addresses kept in a user's EVM claim.
+#[constant]
+pub const EVM_ADDRESS_HISTORY_LEN: usize = 3;
+
+// The maximum number of guardians that can pause the presale.
+#[constant]
+pub const MAX_GUARDIANS: usize = 5;
+
+// The maximum number of steps in the price ladder of a presale.
+#[constant]
+pub const MAX_PRICE_STEPS: usize = 8;
+
+// The size of the presale accounts created by version 6 of the program, before they were PDAs.
+#[constant]
+pub const LEGACY_PRESALE_SPACE: usize = 500;
+
+// The maximum number of decimals of the token being sold, as on most EVM tokens.
+// Token amounts are u128, which leaves room for supplies of over 10^20 whole tokens.
+#[constant]
+pub const MAX_TOKEN_DECIMALS: u8 = 18;
+
+// The time in seconds the recovery authority must wait after initiating a recovery.
+#[constant]
+pub const RECOVERY_TIMELOCK_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+// Feature flags enabling optional subsystems of a presale, set at initialize.
+
+// Purchase receipts and buyer snapshots (buy_tokens_with_receipt, record_snapshot).
+#[constant]
+pub const FEATURE_RECEIPTS: u64 = 1 << 0;
+
+// EVM address claims (claim_evm).
+#[constant]
+pub const FEATURE_EVM_CLAIMS: u64 = 1 << 1;
+
+// Staking (stake_tokens).
+#[constant]
+pub const FEATURE_STAKING: u64 = 1 << 2;
+
+// All known feature flags.
+pub const ALL_FEATURES: u64 = FEATURE_RECEIPTS | FEATURE_EVM_CLAIMS | FEATURE_STAKING;
+
 // Define the main program module.
 #[program]
 pub mod presale_program {
@@ -20,26 +91,202 @@ pub mod presale_program {
     use super::*;
 
     // Function to initialize a new Presale account.
-    pub fn initialize(ctx: Context<Initialize>, payment_wallet: Pubkey, rate: u64) -> Result<()> {
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        presale_id: u64,
+        params: InitializeParams
+    ) -> Result<()> {
+        // Ensure the payment wallet can receive SOL and be withdrawn from later.
+        validate_payment_wallet(&ctx.accounts.payment_wallet)?;
+
+        let presale = &mut ctx.accounts.presale;
+
+        // Store the ID and bump used to derive the presale address.
+        presale.presale_id = presale_id;
+        presale.bump = ctx.bumps.presale;
+
+        // Set the owner and the initial configuration.
+        init_presale(presale, ctx.accounts.owner.key(), params)?;
+
+        // Set up the audit log and record the initial configuration.
+        let audit_log = &mut ctx.accounts.audit_log;
+        audit_log.presale = presale.key();
+        audit_log.bump = ctx.bumps.audit_log;
+        audit_log.record(AuditAction::Initialize, presale.owner, presale.payment_wallet, presale.rate)?;
+
+        Ok(())
+    }
+
+    // Function to initialize a new presale using the configuration of an existing one as a template.
+    // Any field set in the overrides replaces the copied value.
+    pub fn initialize_from(
+        ctx: Context<InitializeFrom>,
+        presale_id: u64,
+        overrides: InitializeFromOverrides
+    ) -> Result<()> {
+        // Ensure only known features are enabled.
+        if let Some(features) = overrides.features {
+            require!(features & !ALL_FEATURES == 0, ErrorCode::InvalidFeatures);
+        }
+
+        // Ensure the withdrawal allowlist fits in the presale account.
+        if let Some(withdrawal_allowlist) = &overrides.withdrawal_allowlist {
+            require!(
+                withdrawal_allowlist.len() <= MAX_WITHDRAWAL_DESTINATIONS,
+                ErrorCode::WithdrawalAllowlistTooLong
+            );
+        }
+
+        let source = &ctx.accounts.source;
+
+        // Ensure token amounts in the smallest unit fit in the presale account.
+        if let Some(token_decimals) = overrides.token_decimals {
+            require!(token_decimals <= MAX_TOKEN_DECIMALS, ErrorCode::InvalidTokenDecimals);
+
+            // The copied rate, price ladder and supply are in the source token's smallest unit,
+            // so a different unit requires all of them to be given in the new one.
+            if token_decimals != source.token_decimals {
+                let config = &overrides.config;
+                require!(
+                    config.rate.is_some() &&
+                        config.price_steps.is_some() &&
+                        config.total_tokens_for_sale.is_some(),
+                    ErrorCode::TokenAmountsNotOverridden
+                );
+            }
+        }
+
         let presale = &mut ctx.accounts.presale;
 
         // Set the owner of the presale to the account initializing it.
         presale.owner = *ctx.accounts.owner.key;
 
-        // Set the initial token rate for the presale.
-        presale.rate = rate;
-
-        // Set the initial payment wallet
-        presale.payment_wallet = payment_wallet;
+        // Store the ID and bump used to derive the presale address.
+        presale.presale_id = presale_id;
+        presale.bump = ctx.bumps.presale;
+
+        // Copy the configuration of the source presale.
+        presale.rate = source.rate;
+        presale.payment_wallet = source.payment_wallet;
+        presale.min_buy_lamports = source.min_buy_lamports;
+        presale.block_cpi_purchases = source.block_cpi_purchases;
+        presale.guardians = source.guardians.clone();
+        presale.lock_evm_claims = source.lock_evm_claims;
+        presale.recovery_authority = source.recovery_authority;
+        presale.price_steps = source.price_steps.clone();
+
+        // Copy the settings that cannot be changed after initialization, unless overridden.
+        presale.withdrawal_allowlist = overrides.withdrawal_allowlist
+            .unwrap_or_else(|| source.withdrawal_allowlist.clone());
+        presale.features = overrides.features.unwrap_or(source.features);
+        presale.token_decimals = overrides.token_decimals.unwrap_or(source.token_decimals);
+
+        // The EVM claim cutoff and snapshot time are absolute times tied to the source sale,
+        // so they start unset and can only be given through the overrides.
+        presale.evm_claim_cutoff = 0;
+        presale.snapshot_time = 0;
+
+        // Copy the supply of tokens for sale, all of which is initially remaining.
+        presale.total_tokens_for_sale = source.total_tokens_for_sale;
+        presale.tokens_remaining = source.total_tokens_for_sale;
 
         // Ensure the presale starts in an active state (not paused).
         presale.is_paused = false;
 
+        // Set up the audit log and record the initial configuration.
+        let audit_log = &mut ctx.accounts.audit_log;
+        audit_log.presale = presale.key();
+        audit_log.bump = ctx.bumps.audit_log;
+        audit_log.record(AuditAction::Initialize, presale.owner, source.key(), presale.rate)?;
+
+        // Apply the overrides on top of the copied configuration.
+        let payment_wallet = &ctx.accounts.payment_wallet;
+        apply_config_update(
+            presale,
+            audit_log,
+            ctx.accounts.owner.key(),
+            overrides.config,
+            Some(payment_wallet)
+        )?;
+
+        // Ensure the resulting payment wallet, copied or overridden, is valid.
+        require_keys_eq!(presale.payment_wallet, payment_wallet.key(), ErrorCode::InvalidPaymentWallet);
+        validate_payment_wallet(payment_wallet)?;
+
+        // Default the withdrawal allowlist to the payment wallet, as in initialize.
+        if presale.withdrawal_allowlist.is_empty() {
+            presale.withdrawal_allowlist = vec![presale.payment_wallet];
+        }
+
+        msg!("InitializeFromLog: Presale: {}", presale.key());
+        msg!("InitializeFromLog: Source: {}", source.key());
+
+        Ok(())
+    }
+
+    // Function to migrate a presale created by version 6 of the program to the current layout.
+    // Version 6 presales are keypair accounts of LEGACY_PRESALE_SPACE bytes holding only the owner,
+    // rate, payment wallet and paused flag, which the current layout cannot read. The account is
+    // grown and rewritten in place with the given configuration, and its audit log is created.
+    pub fn migrate_presale(ctx: Context<MigratePresale>, params: InitializeParams) -> Result<()> {
+        let presale_info = &ctx.accounts.presale;
+
+        // Ensure the account is a presale in the legacy layout, and read its owner and paused flag.
+        let (legacy_owner, legacy_is_paused) = parse_legacy_presale(&presale_info.try_borrow_data()?)?;
+
+        // Ensure only the owner of the legacy presale can migrate it.
+        require_keys_eq!(legacy_owner, ctx.accounts.owner.key(), ErrorCode::Unauthorized);
+
+        // Ensure the payment wallet can receive SOL and be withdrawn from later.
+        validate_payment_wallet(&ctx.accounts.payment_wallet)?;
+
+        // Top up the rent for the current layout and grow the account to it.
+        let new_len = 8 + Presale::INIT_SPACE;
+        let rent_due = Rent::get()?.minimum_balance(new_len).saturating_sub(presale_info.lamports());
+        if rent_due > 0 {
+            invoke(
+                &system_instruction::transfer(ctx.accounts.payer.key, presale_info.key, rent_due),
+                &[
+                    ctx.accounts.payer.to_account_info(),
+                    presale_info.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ]
+            )?;
+        }
+        presale_info.realloc(new_len, true)?;
+
+        // Clear the legacy fields, keeping the discriminator, so the account reads as an empty presale.
+        presale_info.try_borrow_mut_data()?[8..].fill(0);
+        let mut presale = Presale::try_deserialize(&mut &presale_info.try_borrow_data()?[..])?;
+
+        // Set the owner and configuration. Keypair presales have no ID or bump, so both stay 0.
+        init_presale(&mut presale, legacy_owner, params)?;
+
+        // Keep the presale paused if it was paused before the migration.
+        presale.is_paused = legacy_is_paused;
+
+        // Write the migrated presale back to the account.
+        presale.try_serialize(&mut &mut presale_info.try_borrow_mut_data()?[..])?;
+
+        // Set up the audit log and record the migration.
+        let audit_log = &mut ctx.accounts.audit_log;
+        audit_log.presale = presale_info.key();
+        audit_log.bump = ctx.bumps.audit_log;
+        audit_log.record(AuditAction::MigratePresale, legacy_owner, presale.payment_wallet, presale.rate)?;
+
+        msg!("MigrateLog: Presale: {}", presale_info.key());
+
         Ok(())
     }
 
     // Function to allow users to buy tokens during the presale.
     pub fn stake_tokens(ctx: Context<StakeTokens>, amount: u64) -> Result<()> {
+        // Ensure staking is enabled for this presale.
+        require!(ctx.accounts.presale.has_feature(FEATURE_STAKING), ErrorCode::FeatureDisabled);
+
+        // Ensure a non-zero amount is being staked.
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
         // Log this value into the transaction log
         msg!("StakeLog: Buyer: {}", *ctx.accounts.buyer.key);
         msg!("StakeLog: Amount: {}", amount);
@@ -47,10 +294,59 @@ pub mod presale_program {
     }
 
     // Function for users to submit their EVM addresses.
-    pub fn claim_evm(ctx: Context<ClaimEVM>, evm_address: String) -> Result<()> {
+    pub fn claim_evm(ctx: Context<ClaimEVM>, chain_id: u64, evm_address: String) -> Result<()> {
+        // Ensure EVM claims are enabled for this presale.
+        require!(ctx.accounts.presale.has_feature(FEATURE_EVM_CLAIMS), ErrorCode::FeatureDisabled);
+
+        // Ensure an EVM address was provided.
+        require!(!evm_address.trim().is_empty(), ErrorCode::EmptyEvmAddress);
+
+        // Parse the EVM address into its raw bytes, rejecting malformed addresses.
+        let evm_address = evm::parse_evm_address(&evm_address)?;
+
+        // Ensure a chain ID was provided.
+        require!(chain_id > 0, ErrorCode::InvalidChainId);
+
+        // Ensure the claim cutoff, if any, has not passed.
+        let now = Clock::get()?.unix_timestamp;
+        let cutoff = ctx.accounts.presale.evm_claim_cutoff;
+        require!(cutoff == 0 || now < cutoff, ErrorCode::EvmClaimClosed);
+
+        let evm_claim = &mut ctx.accounts.evm_claim;
+
+        if evm_claim.user == Pubkey::default() {
+            // Fill in the claim on the first submission.
+            evm_claim.presale = ctx.accounts.presale.key();
+            evm_claim.user = ctx.accounts.user.key();
+            evm_claim.chain_id = chain_id;
+            evm_claim.bump = ctx.bumps.evm_claim;
+        } else {
+            // Ensure the address can still be changed if the presale locks EVM claims.
+            require!(!ctx.accounts.presale.lock_evm_claims, ErrorCode::EvmAddressLocked);
+
+            // Keep the replaced address in the history, dropping the oldest entry once it is full.
+            if evm_claim.history.len() == EVM_ADDRESS_HISTORY_LEN {
+                evm_claim.history.remove(0);
+            }
+            let previous = EvmAddressChange {
+                evm_address: evm_claim.evm_address,
+                replaced_at: now,
+            };
+            msg!(
+                "ClaimEVMLog: Previous EVM Address: {}",
+                evm::format_evm_address(&previous.evm_address)
+            );
+            evm_claim.history.push(previous);
+        }
+
+        // Store the new EVM address.
+        evm_claim.evm_address = evm_address;
+        evm_claim.updated_at = now;
+
         // Log the user's public key and EVM address.
         msg!("ClaimEVMLog: User: {}", *ctx.accounts.user.key);
-        msg!("ClaimEVMLog: EVM Address: {}", evm_address);
+        msg!("ClaimEVMLog: Chain ID: {}", chain_id);
+        msg!("ClaimEVMLog: EVM Address: {}", evm::format_evm_address(&evm_claim.evm_address));
         Ok(())
     }
 
@@ -59,52 +355,206 @@ pub mod presale_program {
         ctx: Context<BuyTokens>,
         sol_amount: u64,
         stake: bool,
-        evm_address: String
-    ) -> Result<()> {
-        let presale = &mut ctx.accounts.presale;
+        evm_address: String,
+        memo: Option<String>
+    ) -> Result<PurchaseResult> {
+        // Ensure contributions are tracked in receipts when receipts are enabled, so snapshots
+        // cover every purchase.
+        require!(!ctx.accounts.presale.has_feature(FEATURE_RECEIPTS), ErrorCode::ReceiptRequired);
+
+        // Validate the EVM address, memo and payment references supplied by the buyer.
+        validate_purchase_details(&evm_address, memo.as_deref(), ctx.remaining_accounts)?;
+
+        let result = process_purchase(
+            &mut ctx.accounts.presale,
+            &ctx.accounts.buyer,
+            &ctx.accounts.payment_wallet,
+            &ctx.accounts.system_program,
+            &ctx.accounts.instructions,
+            sol_amount
+        )?;
+
+        // Log this value into the transaction log
+        log_purchase(
+            ctx.accounts.buyer.key,
+            &result,
+            stake,
+            &evm_address,
+            memo.as_deref(),
+            ctx.remaining_accounts
+        );
+
+        // Return the purchase result to programs calling this one via CPI.
+        Ok(result)
+    }
 
-        // Ensure the presale is not paused before proceeding.
-        require!(!presale.is_paused, ErrorCode::PresaleIsPaused);
+    // Function to buy an exact amount of tokens, paying at most max_sol for them.
+    pub fn buy_exact_tokens(
+        ctx: Context<BuyTokens>,
+        token_amount: u128,
+        max_sol: u64,
+        stake: bool,
+        evm_address: String,
+        memo: Option<String>
+    ) -> Result<PurchaseResult> {
+        // Ensure contributions are tracked in receipts when receipts are enabled, so snapshots
+        // cover every purchase.
+        require!(!ctx.accounts.presale.has_feature(FEATURE_RECEIPTS), ErrorCode::ReceiptRequired);
+
+        // Validate the EVM address, memo and payment references supplied by the buyer.
+        validate_purchase_details(&evm_address, memo.as_deref(), ctx.remaining_accounts)?;
+
+        // Calculate the SOL needed for the tokens, rounding up so the buyer receives at least token_amount.
+        require!(token_amount > 0, ErrorCode::ZeroAmount);
+        let sol_amount = ctx.accounts.presale.sol_for_tokens(token_amount)?;
+
+        // Ensure the buyer is not charged more than they accepted.
+        require!(sol_amount <= max_sol, ErrorCode::MaxSolExceeded);
+
+        let result = process_purchase(
+            &mut ctx.accounts.presale,
+            &ctx.accounts.buyer,
+            &ctx.accounts.payment_wallet,
+            &ctx.accounts.system_program,
+            &ctx.accounts.instructions,
+            sol_amount
+        )?;
 
-        // Ensure that the payment wallet provides is the correct one.
-        require_keys_eq!(
-            presale.payment_wallet,
-            ctx.accounts.payment_wallet.key(),
-            ErrorCode::InvalidPaymentWallet
+        // Log this value into the transaction log
+        log_purchase(
+            ctx.accounts.buyer.key,
+            &result,
+            stake,
+            &evm_address,
+            memo.as_deref(),
+            ctx.remaining_accounts
         );
 
-        // Perform the SOL transfer
-        let sender = &ctx.accounts.buyer.to_account_info();
-        let receiver = &ctx.accounts.payment_wallet.to_account_info();
+        // Return the purchase result to programs calling this one via CPI.
+        Ok(result)
+    }
+
+    // Function to buy tokens and record the purchase in its own receipt account.
+    pub fn buy_tokens_with_receipt(
+        ctx: Context<BuyTokensWithReceipt>,
+        sol_amount: u64,
+        stake: bool,
+        evm_address: String,
+        memo: Option<String>
+    ) -> Result<PurchaseResult> {
+        // Ensure purchase receipts are enabled for this presale.
+        require!(ctx.accounts.presale.has_feature(FEATURE_RECEIPTS), ErrorCode::FeatureDisabled);
+
+        // Validate the EVM address, memo and payment references supplied by the buyer.
+        validate_purchase_details(&evm_address, memo.as_deref(), ctx.remaining_accounts)?;
+
+        let result = process_purchase(
+            &mut ctx.accounts.presale,
+            &ctx.accounts.buyer,
+            &ctx.accounts.payment_wallet,
+            &ctx.accounts.system_program,
+            &ctx.accounts.instructions,
+            sol_amount
+        )?;
 
-        // Ensure the sender's account is not the same as the receiver's
-        if sender.key() == receiver.key() {
-            return Err(ProgramError::InvalidArgument.into());
+        let buyer_history = &mut ctx.accounts.buyer_history;
+        let receipt = &mut ctx.accounts.receipt;
+
+        // Fill in the buyer history on the first purchase.
+        if buyer_history.purchase_count == 0 {
+            buyer_history.presale = ctx.accounts.presale.key();
+            buyer_history.buyer = ctx.accounts.buyer.key();
+            buyer_history.bump = ctx.bumps.buyer_history;
         }
 
-        // Construct the transfer instruction to the payment wallet
-        let transfer_instruction = system_instruction::transfer(
-            sender.key,
-            receiver.key,
-            sol_amount
-        );
+        // Record the details of this individual purchase.
+        receipt.presale = ctx.accounts.presale.key();
+        receipt.buyer = ctx.accounts.buyer.key();
+        receipt.index = buyer_history.purchase_count;
+        receipt.purchase_id = result.purchase_id;
+        receipt.sol_amount = sol_amount;
+        receipt.rate = result.rate;
+        receipt.token_amount = result.token_amount;
+        receipt.stake = stake;
+        receipt.timestamp = Clock::get()?.unix_timestamp;
+        receipt.bump = ctx.bumps.receipt;
+
+        // Freeze the contribution made before the snapshot time, if it has not been recorded yet.
+        if buyer_history.record_snapshot(ctx.accounts.presale.snapshot_time, receipt.timestamp) {
+            msg!("SnapshotLog: Buyer: {}", buyer_history.buyer);
+            msg!("SnapshotLog: Contribution: {}", buyer_history.snapshot_contribution);
+            msg!("SnapshotLog: Staked: {}", buyer_history.snapshot_staked);
+        }
 
-        // Invoke the transfer instruction
-        invoke(
-            &transfer_instruction,
-            &[
-                sender.to_account_info(),
-                receiver.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ]
+        // Advance the buyer's counter so the next purchase gets a new receipt.
+        buyer_history.purchase_count = math::checked_add(buyer_history.purchase_count, 1)?;
+        buyer_history.total_contributed = math::checked_add(
+            buyer_history.total_contributed,
+            sol_amount
         )?;
 
+        // Add the tokens the buyer opted to stake.
+        if stake {
+            buyer_history.total_staked = math::checked_add(
+                buyer_history.total_staked,
+                result.token_amount
+            )?;
+        }
+
         // Log this value into the transaction log
-        msg!("BuyerLog: Buyer: {}", *ctx.accounts.buyer.key);
-        msg!("BuyerLog: SOL amount: {}", sol_amount);
-        msg!("BuyerLog: Price: ~ {}", presale.rate);
-        msg!("BuyerLog: Stake: ~ {}", stake);
-        msg!("BuyerLog: EVM Address: {}", evm_address);
+        log_purchase(
+            ctx.accounts.buyer.key,
+            &result,
+            stake,
+            &evm_address,
+            memo.as_deref(),
+            ctx.remaining_accounts
+        );
+        msg!("BuyerLog: Receipt: {}", receipt.key());
+        msg!("BuyerLog: Receipt index: {}", receipt.index);
+
+        // Return the purchase result to programs calling this one via CPI.
+        Ok(result)
+    }
+
+    // Function to freeze a buyer's cumulative contribution once the snapshot time is reached.
+    // Anyone can call it, so snapshots do not depend on the team or the buyer.
+    pub fn record_snapshot(ctx: Context<RecordSnapshot>) -> Result<()> {
+        // Ensure purchase receipts (and therefore snapshots) are enabled for this presale.
+        require!(ctx.accounts.presale.has_feature(FEATURE_RECEIPTS), ErrorCode::FeatureDisabled);
+
+        let snapshot_time = ctx.accounts.presale.snapshot_time;
+        let buyer_history = &mut ctx.accounts.buyer_history;
+
+        // Ensure a snapshot time is configured and has been reached.
+        let now = Clock::get()?.unix_timestamp;
+        require!(snapshot_time != 0 && now >= snapshot_time, ErrorCode::SnapshotNotReached);
+
+        // Ensure the snapshot is only recorded once.
+        require!(buyer_history.record_snapshot(snapshot_time, now), ErrorCode::SnapshotAlreadyRecorded);
+
+        // Log the frozen contribution.
+        msg!("SnapshotLog: Buyer: {}", buyer_history.buyer);
+        msg!("SnapshotLog: Contribution: {}", buyer_history.snapshot_contribution);
+        msg!("SnapshotLog: Staked: {}", buyer_history.snapshot_staked);
+
+        Ok(())
+    }
+
+    // Function to log the current presale totals, so dashboards do not have to diff account state.
+    // Anyone can call it, as it does not modify any account.
+    pub fn snapshot_stats(ctx: Context<SnapshotStats>) -> Result<()> {
+        let presale = &ctx.accounts.presale;
+
+        msg!("StatsLog: Total raised: {}", presale.total_raised);
+        msg!("StatsLog: Total tokens for sale: {}", presale.total_tokens_for_sale);
+        msg!("StatsLog: Tokens sold: {}", presale.tokens_sold()?);
+        msg!("StatsLog: Tokens remaining: {}", presale.tokens_remaining);
+        msg!("StatsLog: Purchases: {}", presale.purchase_counter);
+        msg!("StatsLog: Current rate: {}", presale.rate_at(presale.total_raised));
+        msg!("StatsLog: Token decimals: {}", presale.token_decimals);
+        msg!("StatsLog: Milestone: {}%", presale.milestone_percent()?);
+        msg!("StatsLog: Paused: {}", presale.is_paused);
 
         Ok(())
     }
@@ -113,28 +563,77 @@ pub mod presale_program {
     pub fn withdraw_sol(ctx: Context<WithdrawSol>, amount: u64) -> Result<()> {
         let presale = &mut ctx.accounts.presale;
 
-        // Ensure that the caller is the owner of the presale.
-        require_keys_eq!(presale.owner, ctx.accounts.owner.key(), ErrorCode::Unauthorized);
+        // Ensure a non-zero amount is being withdrawn.
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
+        // Ensure the recipient is one of the allowed withdrawal destinations.
+        require!(
+            presale.withdrawal_allowlist.contains(ctx.accounts.recipient.key),
+            ErrorCode::RecipientNotAllowed
+        );
 
         // Deduct the specified amount of SOL from the presale account.
-        **presale.to_account_info().try_borrow_mut_lamports()? -= amount;
+        let presale_info = presale.to_account_info();
+        let mut presale_lamports = presale_info.try_borrow_mut_lamports()?;
+        **presale_lamports = math::checked_sub(**presale_lamports, amount)?;
 
         // Add the specified amount of SOL to the recipient's account.
-        **ctx.accounts.recipient.try_borrow_mut_lamports()? += amount;
+        let mut recipient_lamports = ctx.accounts.recipient.try_borrow_mut_lamports()?;
+        **recipient_lamports = math::checked_add(**recipient_lamports, amount)?;
+
+        // Record the withdrawal in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::WithdrawSol,
+            ctx.accounts.owner.key(),
+            ctx.accounts.recipient.key(),
+            amount.into()
+        )?;
 
         Ok(())
     }
 
     // Function to change the rate of tokens per SOL.
-    pub fn change_rate(ctx: Context<ChangeRate>, new_rate: u64) -> Result<()> {
+    pub fn change_rate(ctx: Context<ChangeRate>, new_rate: u128) -> Result<()> {
         let presale = &mut ctx.accounts.presale;
 
-        // Ensure that the caller is the owner of the presale.
-        require_keys_eq!(presale.owner, ctx.accounts.owner.key(), ErrorCode::Unauthorized);
+        // Ensure the new rate is not zero.
+        require!(new_rate > 0, ErrorCode::InvalidRate);
+
+        // Ensure the base rate is still in use, as it has no effect once a price step applies.
+        require!(!presale.step_in_effect(), ErrorCode::RateLockedByPriceStep);
 
         // Update the rate at which tokens are sold.
         presale.rate = new_rate;
 
+        // Ensure the price ladder still lowers the new rate.
+        presale.validate_price_steps()?;
+
+        // Record the change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::ChangeRate,
+            ctx.accounts.owner.key(),
+            Pubkey::default(),
+            new_rate
+        )?;
+
+        Ok(())
+    }
+
+    // Function to change the minimum amount of SOL accepted per purchase.
+    pub fn change_min_buy(ctx: Context<ChangeMinBuy>, new_min_buy_lamports: u64) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
+
+        // Update the minimum purchase amount.
+        presale.min_buy_lamports = new_min_buy_lamports;
+
+        // Record the change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::ChangeMinBuy,
+            ctx.accounts.owner.key(),
+            Pubkey::default(),
+            new_min_buy_lamports.into()
+        )?;
+
         Ok(())
     }
 
@@ -143,169 +642,1616 @@ pub mod presale_program {
         ctx: Context<ChangePaymentWallet>,
         new_wallet: Pubkey
     ) -> Result<()> {
-        let presale = &mut ctx.accounts.presale;
+        // Ensure the new payment wallet can receive SOL and be withdrawn from later.
+        validate_payment_wallet(&ctx.accounts.new_payment_wallet)?;
 
-        // Ensure that the caller is the owner of the presale.
-        require_keys_eq!(presale.owner, ctx.accounts.owner.key(), ErrorCode::Unauthorized);
+        let presale = &mut ctx.accounts.presale;
 
-        // Update the rate at which tokens are sold.
+        // Update the wallet receiving the SOL payments.
         presale.payment_wallet = new_wallet;
 
+        // Record the change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::ChangePaymentWallet,
+            ctx.accounts.owner.key(),
+            new_wallet,
+            0
+        )?;
+
         Ok(())
     }
 
-    // Function to pause or resume the presale.
-    pub fn pause_presale(ctx: Context<PausePresale>, pause: bool) -> Result<()> {
+    // Function to add a guardian, who can pause (but not resume) the presale.
+    pub fn add_guardian(ctx: Context<ManageGuardians>, guardian: Pubkey) -> Result<()> {
         let presale = &mut ctx.accounts.presale;
 
-        // Ensure that the caller is the owner of the presale.
-        require_keys_eq!(presale.owner, ctx.accounts.owner.key(), ErrorCode::Unauthorized);
+        // Ensure the guardian is not already in the set and the set is not full.
+        require!(!presale.guardians.contains(&guardian), ErrorCode::GuardianAlreadyAdded);
+        require!(presale.guardians.len() < MAX_GUARDIANS, ErrorCode::TooManyGuardians);
 
-        // Set the presale's paused state according to the function call.
-        presale.is_paused = pause;
+        // Add the guardian to the set.
+        presale.guardians.push(guardian);
+
+        // Record the change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::AddGuardian,
+            ctx.accounts.owner.key(),
+            guardian,
+            presale.guardians.len() as u128
+        )?;
 
         Ok(())
     }
-}
-
-// Account structs used in different transactions.
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    // Define the presale account that will be created and owned by the caller.
-    #[account(init, payer = owner, space = 500)]
-    pub presale: Account<'info, Presale>,
+    // Function to remove a guardian from the set.
+    pub fn remove_guardian(ctx: Context<ManageGuardians>, guardian: Pubkey) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
 
-    // The account paying for the transaction and owning the new presale account.
-    #[account(mut)]
-    pub owner: Signer<'info>,
+        // Ensure the guardian is in the set.
+        let position = presale.guardians
+            .iter()
+            .position(|key| *key == guardian)
+            .ok_or(ErrorCode::GuardianNotFound)?;
+
+        // Remove the guardian from the set.
+        presale.guardians.remove(position);
+
+        // Record the change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::RemoveGuardian,
+            ctx.accounts.owner.key(),
+            guardian,
+            presale.guardians.len() as u128
+        )?;
 
-    // Reference to the system program, used for creating accounts.
-    pub system_program: Program<'info, System>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct BuyTokens<'info> {
-    // The presale account from which tokens are being bought.
-    #[account(mut)]
-    pub presale: Account<'info, Presale>,
+    // Function to apply several configuration changes atomically.
+    pub fn update_config(ctx: Context<UpdateConfig>, update: ConfigUpdate) -> Result<()> {
+        apply_config_update(
+            &mut ctx.accounts.presale,
+            &mut ctx.accounts.audit_log,
+            ctx.accounts.owner.key(),
+            update,
+            ctx.accounts.new_payment_wallet.as_ref()
+        )
+    }
 
-    // The buyer of the tokens.
-    // The #[account(mut, signer)] attribute on sender ensures that the account is both mutable (to deduct SOL)
-    // and a signer of the transaction (implying that the caller of this function must be the sender).
-    #[account(mut, signer)]
-    pub buyer: Signer<'info>,
+    // Function to permanently give up control of the presale.
+    pub fn renounce_ownership(ctx: Context<RenounceOwnership>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
 
-    /// CHECK:` doc comment explaining why no checks through types are necessary.
-    #[account(mut)]
-    pub payment_wallet: AccountInfo<'info>,
+        // Set the owner to the system program ID, which can never sign, and remove all guardians,
+        // so every admin instruction is permanently disabled.
+        presale.owner = Pubkey::default();
+        presale.guardians.clear();
+
+        // Remove the recovery authority so ownership cannot be reclaimed either.
+        presale.recovery_authority = Pubkey::default();
+        presale.recovery_started_at = 0;
+
+        // Record the renouncement in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::RenounceOwnership,
+            ctx.accounts.owner.key(),
+            Pubkey::default(),
+            0
+        )?;
 
-    // Add the system program account to facilitate the transfer of SOL
-    pub system_program: Program<'info, System>,
-}
+        // Log the renouncement.
+        msg!("RenounceLog: Presale: {}", presale.key());
+        msg!("RenounceLog: Previous owner: {}", ctx.accounts.owner.key());
 
-#[derive(Accounts)]
-pub struct StakeTokens<'info> {
-    // The presale account
-    #[account(mut)]
-    pub presale: Account<'info, Presale>,
+        Ok(())
+    }
 
-    // The buyer of the tokens.
-    // The #[account(mut, signer)] attribute on sender ensures that the account is both mutable (to deduct SOL)
-    // and a signer of the transaction (implying that the caller of this function must be the sender).
-    #[account(mut, signer)]
-    pub buyer: Signer<'info>,
-}
+    // Function to change the recovery authority, who can take over ownership after a timelock.
+    pub fn change_recovery_authority(
+        ctx: Context<ChangeRecoveryAuthority>,
+        new_recovery_authority: Pubkey
+    ) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
 
-#[derive(Accounts)]
-pub struct ClaimEVM<'info> {
-    // The presale account
-    #[account(mut)]
-    pub presale: Account<'info, Presale>,
+        // Update the recovery authority and cancel any recovery started by the previous one.
+        // Pubkey::default() removes the recovery authority.
+        presale.recovery_authority = new_recovery_authority;
+        presale.recovery_started_at = 0;
+
+        // Record the change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::ChangeRecoveryAuthority,
+            ctx.accounts.owner.key(),
+            new_recovery_authority,
+            0
+        )?;
 
-    // The user submitting their EVM address.
-    // The #[account(mut, signer)] attribute on sender ensures that the account is both mutable (to deduct SOL)
-    // and a signer of the transaction (implying that the caller of this function must be the sender).
-    #[account(mut, signer)]
-    pub user: Signer<'info>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct WithdrawSol<'info> {
-    // The presale account from which SOL will be withdrawn.
-    #[account(mut)]
-    pub presale: Account<'info, Presale>,
+    // Function for the recovery authority to start the recovery timelock.
+    pub fn initiate_recovery(ctx: Context<InitiateRecovery>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
 
-    // The recipient account to which SOL will be sent.
-    #[account(mut)]
-    pub recipient: Signer<'info>,
+        // Ensure no recovery is already in progress.
+        require!(presale.recovery_started_at == 0, ErrorCode::RecoveryAlreadyInitiated);
 
-    // The owner of the presale account who is authorized to perform withdrawals.
-    pub owner: Signer<'info>,
-}
+        // Start the timelock.
+        let now = Clock::get()?.unix_timestamp;
+        presale.recovery_started_at = now;
 
-#[derive(Accounts)]
-pub struct ChangeRate<'info> {
-    // The presale account for which the token sale rate will be changed.
-    #[account(mut)]
-    pub presale: Account<'info, Presale>,
+        // Record the start of the recovery in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::InitiateRecovery,
+            ctx.accounts.recovery_authority.key(),
+            presale.owner,
+            now as u128
+        )?;
 
-    // The owner of the presale account, authorized to change the rate.
-    pub owner: Signer<'info>,
-}
+        // Log the recovery so the owner can react within the timelock.
+        msg!("RecoveryLog: Initiated by: {}", ctx.accounts.recovery_authority.key());
+        msg!("RecoveryLog: Completable at: {}", now.saturating_add(RECOVERY_TIMELOCK_SECONDS));
 
-#[derive(Accounts)]
-pub struct ChangePaymentWallet<'info> {
-    // The presale account for which the payment wallet will be changed.
-    #[account(mut)]
-    pub presale: Account<'info, Presale>,
+        Ok(())
+    }
 
-    // The owner of the presale account, authorized to change the payment wallet.
-    pub owner: Signer<'info>,
-}
+    // Function for the owner to cancel a recovery in progress.
+    pub fn cancel_recovery(ctx: Context<CancelRecovery>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
 
-#[derive(Accounts)]
-pub struct PausePresale<'info> {
-    // The presale account that will be paused or resumed.
-    #[account(mut)]
-    pub presale: Account<'info, Presale>,
+        // Ensure a recovery is in progress.
+        require!(presale.recovery_started_at != 0, ErrorCode::NoRecoveryInProgress);
 
-    // The owner of the presale account, authorized to pause or resume it.
-    pub owner: Signer<'info>,
-}
+        // Cancel the recovery.
+        presale.recovery_started_at = 0;
 
-// The main Presale account structure.
-#[account]
-pub struct Presale {
-    // The public key of the owner of the presale.
-    pub owner: Pubkey,
+        // Record the cancellation in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::CancelRecovery,
+            ctx.accounts.owner.key(),
+            presale.recovery_authority,
+            0
+        )?;
 
-    // The rate of tokens per SOL.
-    pub rate: u64,
+        Ok(())
+    }
 
-    // The wallet for sending the SOL payments to
-    pub payment_wallet: Pubkey,
+    // Function for the recovery authority to take over ownership once the timelock has passed.
+    pub fn complete_recovery(ctx: Context<CompleteRecovery>) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
 
-    // Flag indicating whether the presale is paused.
-    pub is_paused: bool,
-}
+        // Ensure a recovery is in progress and its timelock has passed.
+        require!(presale.recovery_started_at != 0, ErrorCode::NoRecoveryInProgress);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= presale.recovery_started_at.saturating_add(RECOVERY_TIMELOCK_SECONDS),
+            ErrorCode::RecoveryTimelockActive
+        );
 
-// Custom error codes used in the program.
-#[error_code]
-pub enum ErrorCode {
-    // Indicates that the presale is currently paused.
-    #[msg("The presale is currently paused.")]
-    PresaleIsPaused,
+        // Transfer ownership to the recovery authority.
+        let previous_owner = presale.owner;
+        presale.owner = presale.recovery_authority;
+        presale.recovery_started_at = 0;
+
+        // Record the ownership change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::CompleteRecovery,
+            ctx.accounts.recovery_authority.key(),
+            previous_owner,
+            0
+        )?;
 
-    // Indicates an overflow error, likely during token allocation calculation.
-    #[msg("Operation overflowed.")]
-    Overflow,
+        // Log the ownership change.
+        msg!("RecoveryLog: Previous owner: {}", previous_owner);
+        msg!("RecoveryLog: New owner: {}", presale.owner);
 
-    // Indicates an underflow error, likely during token allocation calculation.
-    #[msg("Operation underflowed.")]
-    Underflow,
+        Ok(())
+    }
 
-    // Indicates an unauthorized attempt to perform an operation.
+    // Function to pause or resume the presale.
+    pub fn pause_presale(ctx: Context<PausePresale>, pause: bool) -> Result<()> {
+        let presale = &mut ctx.accounts.presale;
+
+        // Ensure that only the owner can resume the presale; guardians can only pause it.
+        if ctx.accounts.authority.key() != presale.owner {
+            require!(pause, ErrorCode::GuardianCannotUnpause);
+        }
+
+        // Set the presale's paused state according to the function call.
+        presale.is_paused = pause;
+
+        // Record the change in the audit log.
+        ctx.accounts.audit_log.record(
+            AuditAction::PausePresale,
+            ctx.accounts.authority.key(),
+            Pubkey::default(),
+            pause as u128
+        )?;
+
+        Ok(())
+    }
+}
+
+// Reads the owner and paused flag of a presale account in the version 6 layout:
+// discriminator, owner, rate (u64), payment wallet and paused flag, padded to LEGACY_PRESALE_SPACE.
+fn parse_legacy_presale(data: &[u8]) -> Result<(Pubkey, bool)> {
+    require!(data.len() == LEGACY_PRESALE_SPACE, ErrorCode::NotLegacyPresale);
+    require!(
+        data[..8] == Presale::DISCRIMINATOR,
+        anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch
+    );
+
+    let owner = Pubkey::try_from(&data[8..40]).map_err(|_| ErrorCode::NotLegacyPresale)?;
+    let is_paused = data[80] != 0;
+
+    Ok((owner, is_paused))
+}
+
+// Sets the owner and initial configuration of a new presale, validating the parameters.
+fn init_presale(presale: &mut Presale, owner: Pubkey, params: InitializeParams) -> Result<()> {
+    // Ensure the initial rate is not zero.
+    require!(params.rate > 0, ErrorCode::InvalidRate);
+
+    // Ensure there are tokens to sell.
+    require!(params.total_tokens_for_sale > 0, ErrorCode::ZeroAmount);
+
+    // Ensure only known features are enabled.
+    require!(params.features & !ALL_FEATURES == 0, ErrorCode::InvalidFeatures);
+
+    // Ensure token amounts in the smallest unit fit in the presale account.
+    require!(params.token_decimals <= MAX_TOKEN_DECIMALS, ErrorCode::InvalidTokenDecimals);
+
+    // Ensure the withdrawal allowlist fits in the presale account.
+    require!(
+        params.withdrawal_allowlist.len() <= MAX_WITHDRAWAL_DESTINATIONS,
+        ErrorCode::WithdrawalAllowlistTooLong
+    );
+
+    // Set the owner of the presale.
+    presale.owner = owner;
+
+    // Set the initial token rate for the presale.
+    presale.rate = params.rate;
+
+    // Set the initial payment wallet
+    presale.payment_wallet = params.payment_wallet;
+
+    // Set the minimum amount of SOL accepted per purchase.
+    presale.min_buy_lamports = params.min_buy_lamports;
+
+    // Set the supply of tokens for sale, all of which is initially remaining.
+    presale.total_tokens_for_sale = params.total_tokens_for_sale;
+    presale.tokens_remaining = params.total_tokens_for_sale;
+    presale.token_decimals = params.token_decimals;
+
+    // Set the destinations SOL can be withdrawn to, defaulting to the payment wallet.
+    // The allowlist cannot be changed afterwards, so a compromised owner key cannot redirect funds.
+    presale.withdrawal_allowlist = if params.withdrawal_allowlist.is_empty() {
+        vec![params.payment_wallet]
+    } else {
+        params.withdrawal_allowlist
+    };
+
+    // Set the optional subsystems enabled for this presale. They cannot be changed afterwards.
+    presale.features = params.features;
+
+    // Ensure the presale starts in an active state (not paused).
+    presale.is_paused = false;
+
+    Ok(())
+}
+
+// Checks the buyer-provided details attached to a purchase.
+// Any remaining accounts are Solana Pay reference keys, used by the buyer's wallet to find the transaction.
+fn validate_purchase_details(
+    evm_address: &str,
+    memo: Option<&str>,
+    references: &[AccountInfo]
+) -> Result<()> {
+    // Ensure an EVM address was provided.
+    require!(!evm_address.trim().is_empty(), ErrorCode::EmptyEvmAddress);
+
+    // Ensure the EVM address is well formed.
+    evm::parse_evm_address(evm_address)?;
+
+    // Ensure the memo, if any, is not too long.
+    if let Some(memo) = memo {
+        require!(memo.len() <= MAX_MEMO_LEN, ErrorCode::MemoTooLong);
+    }
+
+    // Ensure the payment references are read-only, as they are only there to be found.
+    for reference in references {
+        require!(!reference.is_writable, ErrorCode::InvalidPaymentReference);
+    }
+
+    Ok(())
+}
+
+// Logs the details of a purchase into the transaction log.
+fn log_purchase(
+    buyer: &Pubkey,
+    result: &PurchaseResult,
+    stake: bool,
+    evm_address: &str,
+    memo: Option<&str>,
+    references: &[AccountInfo]
+) {
+    msg!("BuyerLog: Buyer: {}", buyer);
+    msg!("BuyerLog: SOL amount: {}", result.sol_amount);
+    msg!("BuyerLog: Price: ~ {}", result.rate);
+    msg!("BuyerLog: Token amount: {}", result.token_amount);
+    msg!("BuyerLog: Token decimals: {}", result.token_decimals);
+    msg!("BuyerLog: Stake: ~ {}", stake);
+    msg!("BuyerLog: EVM Address: {}", evm_address);
+    msg!("BuyerLog: Purchase ID: {}", result.purchase_id);
+    if let Some(memo) = memo {
+        msg!("BuyerLog: Memo: {}", memo);
+    }
+    for reference in references {
+        msg!("BuyerLog: Reference: {}", reference.key);
+    }
+}
+
+// Shared purchase logic: checks the presale state, transfers the SOL to the payment wallet
+// and returns the result of the purchase.
+fn process_purchase<'info>(
+    presale: &mut Account<'info, Presale>,
+    buyer: &Signer<'info>,
+    payment_wallet: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    instructions: &AccountInfo<'info>,
+    sol_amount: u64
+) -> Result<PurchaseResult> {
+    // Ensure the presale is not paused before proceeding.
+    require!(!presale.is_paused, ErrorCode::PresaleIsPaused);
+
+    // If enabled, ensure the purchase is a top-level instruction of this program and not a CPI.
+    if presale.block_cpi_purchases {
+        let current_index = load_current_index_checked(instructions)?;
+        let current_instruction = load_instruction_at_checked(current_index as usize, instructions)?;
+        require_keys_eq!(current_instruction.program_id, crate::ID, ErrorCode::CpiPurchaseNotAllowed);
+    }
+
+    // Ensure a non-zero amount of SOL is being paid.
+    require!(sol_amount > 0, ErrorCode::ZeroAmount);
+
+    // Ensure the purchase is not below the minimum amount.
+    require!(sol_amount >= presale.min_buy_lamports, ErrorCode::BelowMinimumPurchase);
+
+    // Ensure that the payment wallet provides is the correct one.
+    require_keys_eq!(presale.payment_wallet, payment_wallet.key(), ErrorCode::InvalidPaymentWallet);
+
+    // Ensure the payment wallet is still a plain wallet. Its balance is not checked here,
+    // so purchases still work after the wallet has been swept to zero.
+    check_payment_wallet_kind(payment_wallet)?;
+
+    // Calculate the tokens allocated for the SOL paid, following the price ladder.
+    let rate = presale.rate_at(presale.total_raised);
+    let token_amount = presale.tokens_for_sol(sol_amount)?;
+    require!(token_amount > 0, ErrorCode::ZeroAmount);
+
+    // Ensure enough tokens remain, so the sale ends once the supply is sold out.
+    require!(token_amount <= presale.tokens_remaining, ErrorCode::InsufficientTokensRemaining);
+    let previous_milestone = presale.milestone_percent()?;
+    presale.tokens_remaining = math::checked_sub(presale.tokens_remaining, token_amount)?;
+
+    // Perform the SOL transfer
+    let sender = &buyer.to_account_info();
+    let receiver = &payment_wallet.to_account_info();
+
+    // Ensure the sender's account is not the same as the receiver's
+    if sender.key() == receiver.key() {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+
+    // Construct the transfer instruction to the payment wallet
+    let transfer_instruction = system_instruction::transfer(sender.key, receiver.key, sol_amount);
+
+    // Invoke the transfer instruction
+    invoke(
+        &transfer_instruction,
+        &[sender.to_account_info(), receiver.to_account_info(), system_program.to_account_info()]
+    )?;
+
+    // Add the SOL paid to the total raised, which may move the presale to the next price step.
+    presale.total_raised = math::checked_add(presale.total_raised, sol_amount)?;
+
+    // Assign the next global sequence number to this purchase.
+    let purchase_id = presale.purchase_counter;
+    presale.purchase_counter = math::checked_add(purchase_id, 1)?;
+
+    // Log the highest milestone reached if this purchase crossed one.
+    let milestone = presale.milestone_percent()?;
+    if milestone > previous_milestone {
+        msg!("MilestoneLog: Milestone: {}%", milestone);
+        msg!("MilestoneLog: Total raised: {}", presale.total_raised);
+        msg!("MilestoneLog: Tokens sold: {}", presale.tokens_sold()?);
+    }
+
+    Ok(PurchaseResult {
+        purchase_id,
+        sol_amount,
+        rate,
+        token_amount,
+        token_decimals: presale.token_decimals,
+    })
+}
+
+// Checks that a payment wallet is a plain system account.
+// Program-owned accounts and executables may be unable to sign withdrawals of the SOL they receive.
+fn check_payment_wallet_kind(payment_wallet: &AccountInfo) -> Result<()> {
+    // Ensure the wallet is owned by the system program.
+    require_keys_eq!(
+        *payment_wallet.owner,
+        System::id(),
+        ErrorCode::PaymentWalletNotSystemOwned
+    );
+
+    // Ensure the wallet is not an executable account.
+    require!(!payment_wallet.executable, ErrorCode::PaymentWalletExecutable);
+
+    Ok(())
+}
+
+// Checks that a payment wallet being configured is a plain, rent-exempt system account.
+fn validate_payment_wallet(payment_wallet: &AccountInfo) -> Result<()> {
+    check_payment_wallet_kind(payment_wallet)?;
+
+    // Ensure the wallet holds enough lamports to be rent-exempt.
+    let rent = Rent::get()?;
+    require!(
+        rent.is_exempt(payment_wallet.lamports(), payment_wallet.data_len()),
+        ErrorCode::PaymentWalletNotRentExempt
+    );
+
+    Ok(())
+}
+
+// Checks that the snapshot time can be changed from current to new at the given time.
+// It cannot be changed once reached, as buyer snapshots may already be recorded at that time,
+// and it cannot be set in the past, as purchases made after it would be counted.
+fn check_snapshot_time_change(current: i64, new: i64, now: i64) -> Result<()> {
+    require!(current == 0 || now < current, ErrorCode::SnapshotTimeReached);
+    require!(new == 0 || new > now, ErrorCode::SnapshotTimeInPast);
+
+    Ok(())
+}
+
+// Applies a set of configuration changes to a presale, recording each one in its audit log.
+fn apply_config_update(
+    presale: &mut Presale,
+    audit_log: &mut AuditLog,
+    authority: Pubkey,
+    update: ConfigUpdate,
+    new_payment_wallet: Option<&AccountInfo>
+) -> Result<()> {
+    // The price ladder is checked against the base rate once both are updated.
+    let pricing_changed = update.rate.is_some() || update.price_steps.is_some();
+
+    // Update the rate at which tokens are sold, if provided.
+    // It has no effect once a price step applies, so it cannot be changed then.
+    if let Some(rate) = update.rate {
+        require!(rate > 0, ErrorCode::InvalidRate);
+        require!(!presale.step_in_effect(), ErrorCode::RateLockedByPriceStep);
+        presale.rate = rate;
+        audit_log.record(AuditAction::ChangeRate, authority, Pubkey::default(), rate)?;
+    }
+
+    // Update the payment wallet, if provided.
+    // The wallet account must be provided so it can be validated.
+    if let Some(payment_wallet) = update.payment_wallet {
+        let wallet_account = new_payment_wallet.ok_or(ErrorCode::InvalidPaymentWallet)?;
+        require_keys_eq!(wallet_account.key(), payment_wallet, ErrorCode::InvalidPaymentWallet);
+        validate_payment_wallet(wallet_account)?;
+        presale.payment_wallet = payment_wallet;
+        audit_log.record(AuditAction::ChangePaymentWallet, authority, payment_wallet, 0)?;
+    }
+
+    // Update the minimum purchase amount, if provided.
+    if let Some(min_buy_lamports) = update.min_buy_lamports {
+        presale.min_buy_lamports = min_buy_lamports;
+        audit_log.record(AuditAction::ChangeMinBuy, authority, Pubkey::default(), min_buy_lamports.into())?;
+    }
+
+    // Update the paused state, if provided.
+    if let Some(is_paused) = update.is_paused {
+        presale.is_paused = is_paused;
+        audit_log.record(AuditAction::PausePresale, authority, Pubkey::default(), is_paused as u128)?;
+    }
+
+    // Update the CPI purchase guard, if provided.
+    if let Some(block_cpi_purchases) = update.block_cpi_purchases {
+        presale.block_cpi_purchases = block_cpi_purchases;
+        audit_log.record(
+            AuditAction::ChangeCpiGuard,
+            authority,
+            Pubkey::default(),
+            block_cpi_purchases as u128
+        )?;
+    }
+
+    // Update the EVM claim cutoff, if provided.
+    if let Some(evm_claim_cutoff) = update.evm_claim_cutoff {
+        presale.evm_claim_cutoff = evm_claim_cutoff;
+        audit_log.record(
+            AuditAction::ChangeEvmClaimCutoff,
+            authority,
+            Pubkey::default(),
+            evm_claim_cutoff as u128
+        )?;
+    }
+
+    // Update the EVM claim lock, if provided.
+    if let Some(lock_evm_claims) = update.lock_evm_claims {
+        presale.lock_evm_claims = lock_evm_claims;
+        audit_log.record(
+            AuditAction::ChangeEvmClaimLock,
+            authority,
+            Pubkey::default(),
+            lock_evm_claims as u128
+        )?;
+    }
+
+    // Update the snapshot time, if provided.
+    if let Some(snapshot_time) = update.snapshot_time {
+        check_snapshot_time_change(presale.snapshot_time, snapshot_time, Clock::get()?.unix_timestamp)?;
+        presale.snapshot_time = snapshot_time;
+        audit_log.record(
+            AuditAction::ChangeSnapshotTime,
+            authority,
+            Pubkey::default(),
+            snapshot_time as u128
+        )?;
+    }
+
+    // Update the supply of tokens for sale, if provided.
+    if let Some(total_tokens_for_sale) = update.total_tokens_for_sale {
+        presale.set_total_tokens_for_sale(total_tokens_for_sale)?;
+        audit_log.record(
+            AuditAction::ChangeTokensForSale,
+            authority,
+            Pubkey::default(),
+            total_tokens_for_sale
+        )?;
+    }
+
+    // Update the price ladder, if provided.
+    if let Some(price_steps) = update.price_steps {
+        // Ensure the ladder fits in the presale account.
+        require!(price_steps.len() <= MAX_PRICE_STEPS, ErrorCode::TooManyPriceSteps);
+
+        audit_log.record(
+            AuditAction::ChangePriceSteps,
+            authority,
+            Pubkey::default(),
+            price_steps.len() as u128
+        )?;
+        presale.price_steps = price_steps;
+    }
+
+    // Ensure the price ladder is consistent with the base rate.
+    if pricing_changed {
+        presale.validate_price_steps()?;
+    }
+
+    Ok(())
+}
+
+// Account structs used in different transactions.
+
+#[derive(Accounts)]
+#[instruction(presale_id: u64, params: InitializeParams)]
+pub struct Initialize<'info> {
+    // Define the presale account that will be created and owned by the caller.
+    // Its address is derived from the owner and an ID chosen by the owner, so clients can find it.
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Presale::INIT_SPACE,
+        seeds = [b"presale", owner.key().as_ref(), &presale_id.to_le_bytes()],
+        bump
+    )]
+    pub presale: Account<'info, Presale>,
+
+    // The account that will own the new presale account.
+    // It only needs to sign, so it can be a governance PDA signing through a proposal.
+    pub owner: Signer<'info>,
+
+    /// CHECK: Only its owner, executable flag and balance are read, in validate_payment_wallet.
+    #[account(address = params.payment_wallet @ ErrorCode::InvalidPaymentWallet)]
+    pub payment_wallet: AccountInfo<'info>,
+
+    // The audit log of privileged calls made on the new presale account.
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + AuditLog::INIT_SPACE,
+        seeds = [b"audit", presale.key().as_ref()],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The account paying the rent for the new presale and audit log accounts.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Reference to the system program, used for creating accounts.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(presale_id: u64)]
+pub struct InitializeFrom<'info> {
+    // The existing presale whose configuration is copied.
+    // It must belong to the same owner, since its guardians and withdrawal allowlist are copied too.
+    #[account(has_one = owner @ ErrorCode::Unauthorized)]
+    pub source: Account<'info, Presale>,
+
+    // Define the presale account that will be created and owned by the caller.
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Presale::INIT_SPACE,
+        seeds = [b"presale", owner.key().as_ref(), &presale_id.to_le_bytes()],
+        bump
+    )]
+    pub presale: Account<'info, Presale>,
+
+    // The account that will own the new presale account.
+    pub owner: Signer<'info>,
+
+    /// CHECK: Only its owner, executable flag and balance are read, in validate_payment_wallet.
+    /// It must match the payment wallet of the new presale, copied or overridden.
+    pub payment_wallet: AccountInfo<'info>,
+
+    // The audit log of privileged calls made on the new presale account.
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + AuditLog::INIT_SPACE,
+        seeds = [b"audit", presale.key().as_ref()],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The account paying the rent for the new presale and audit log accounts.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Reference to the system program, used for creating accounts.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(params: InitializeParams)]
+pub struct MigratePresale<'info> {
+    /// CHECK: A presale in the legacy layout, which cannot be deserialized as Presale.
+    /// Its size, discriminator and owner are checked in migrate_presale.
+    #[account(mut, owner = crate::ID)]
+    pub presale: AccountInfo<'info>,
+
+    // The owner of the legacy presale, authorized to migrate it.
+    pub owner: Signer<'info>,
+
+    /// CHECK: Only its owner, executable flag and balance are read, in validate_payment_wallet.
+    #[account(address = params.payment_wallet @ ErrorCode::InvalidPaymentWallet)]
+    pub payment_wallet: AccountInfo<'info>,
+
+    // The audit log of privileged calls made on the presale account.
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + AuditLog::INIT_SPACE,
+        seeds = [b"audit", presale.key().as_ref()],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The account paying the rent for the larger presale account and the audit log account.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Reference to the system program, used for paying rent and creating accounts.
+    pub system_program: Program<'info, System>,
+}
+
+// Any remaining accounts are read-only Solana Pay reference keys, logged with the purchase.
+#[derive(Accounts)]
+pub struct BuyTokens<'info> {
+    // The presale account from which tokens are being bought.
+    #[account(mut)]
+    pub presale: Account<'info, Presale>,
+
+    // The buyer of the tokens.
+    // The #[account(mut, signer)] attribute on sender ensures that the account is both mutable (to deduct SOL)
+    // and a signer of the transaction (implying that the caller of this function must be the sender).
+    #[account(mut, signer)]
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Validated against the payment wallet stored in the presale account.
+    /// Only its owner and executable flag are read, in check_payment_wallet_kind.
+    #[account(mut)]
+    pub payment_wallet: AccountInfo<'info>,
+
+    // Add the system program account to facilitate the transfer of SOL
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The instructions sysvar, used to detect purchases made via CPI.
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+}
+
+// Any remaining accounts are read-only Solana Pay reference keys, logged with the purchase.
+#[derive(Accounts)]
+pub struct BuyTokensWithReceipt<'info> {
+    // The presale account from which tokens are being bought.
+    #[account(mut)]
+    pub presale: Account<'info, Presale>,
+
+    // The buyer of the tokens, whose SOL is used for the purchase.
+    #[account(mut, signer)]
+    pub buyer: Signer<'info>,
+
+    // The account paying the rent for the history and receipt accounts.
+    // This can be the buyer, or a sponsor covering the rent on the buyer's behalf.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // The buyer's purchase counter for this presale, created on the first purchase.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + BuyerHistory::INIT_SPACE,
+        seeds = [b"buyer", presale.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub buyer_history: Account<'info, BuyerHistory>,
+
+    // The receipt for this purchase, seeded with the buyer's current purchase count.
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + PurchaseReceipt::INIT_SPACE,
+        seeds = [
+            b"receipt",
+            presale.key().as_ref(),
+            buyer.key().as_ref(),
+            &buyer_history.purchase_count.to_le_bytes(),
+        ],
+        bump
+    )]
+    pub receipt: Account<'info, PurchaseReceipt>,
+
+    /// CHECK: Validated against the payment wallet stored in the presale account.
+    /// Only its owner and executable flag are read, in check_payment_wallet_kind.
+    #[account(mut)]
+    pub payment_wallet: AccountInfo<'info>,
+
+    // Add the system program account to facilitate the transfer of SOL
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The instructions sysvar, used to detect purchases made via CPI.
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecordSnapshot<'info> {
+    // The presale account defining the snapshot time.
+    pub presale: Account<'info, Presale>,
+
+    // The buyer history whose contribution will be frozen.
+    #[account(mut, has_one = presale)]
+    pub buyer_history: Account<'info, BuyerHistory>,
+}
+
+#[derive(Accounts)]
+pub struct SnapshotStats<'info> {
+    // The presale account whose totals will be logged.
+    pub presale: Account<'info, Presale>,
+}
+
+#[derive(Accounts)]
+pub struct StakeTokens<'info> {
+    // The presale account
+    #[account(mut)]
+    pub presale: Account<'info, Presale>,
+
+    // The buyer of the tokens.
+    // The #[account(mut, signer)] attribute on sender ensures that the account is both mutable (to deduct SOL)
+    // and a signer of the transaction (implying that the caller of this function must be the sender).
+    #[account(mut, signer)]
+    pub buyer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(chain_id: u64)]
+pub struct ClaimEVM<'info> {
+    // The presale account
+    #[account(mut)]
+    pub presale: Account<'info, Presale>,
+
+    // The user submitting their EVM address.
+    pub user: Signer<'info>,
+
+    // The account paying the rent for the EVM claim account.
+    // This can be the user, or a sponsor covering the rent on the user's behalf.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // The user's EVM claim for this presale and chain, created on the first submission.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + EvmClaim::INIT_SPACE,
+        seeds = [b"evm", presale.key().as_ref(), user.key().as_ref(), &chain_id.to_le_bytes()],
+        bump
+    )]
+    pub evm_claim: Account<'info, EvmClaim>,
+
+    // Reference to the system program, used for creating the EVM claim account.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSol<'info> {
+    // The presale account from which SOL will be withdrawn.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    /// CHECK: The recipient account to which SOL will be sent, validated against the withdrawal allowlist.
+    #[account(mut)]
+    pub recipient: AccountInfo<'info>,
+
+    // The owner of the presale account who is authorized to perform withdrawals.
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeRate<'info> {
+    // The presale account for which the token sale rate will be changed.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to change the rate.
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeMinBuy<'info> {
+    // The presale account for which the minimum purchase amount will be changed.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to change the minimum purchase amount.
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_wallet: Pubkey)]
+pub struct ChangePaymentWallet<'info> {
+    // The presale account for which the payment wallet will be changed.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to change the payment wallet.
+    pub owner: Signer<'info>,
+
+    /// CHECK: Only its owner, executable flag and balance are read, in validate_payment_wallet.
+    #[account(address = new_wallet @ ErrorCode::InvalidPaymentWallet)]
+    pub new_payment_wallet: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageGuardians<'info> {
+    // The presale account whose guardian set will be changed.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to manage the guardians.
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    // The presale account whose configuration will be updated.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to update its configuration.
+    pub owner: Signer<'info>,
+
+    /// CHECK: Only its owner, executable flag and balance are read, in validate_payment_wallet.
+    /// Required when the update changes the payment wallet, and must match it.
+    pub new_payment_wallet: Option<AccountInfo<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct RenounceOwnership<'info> {
+    // The presale account whose ownership will be renounced.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to renounce it.
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeRecoveryAuthority<'info> {
+    // The presale account for which the recovery authority will be changed.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to change the recovery authority.
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitiateRecovery<'info> {
+    // The presale account whose ownership will be recovered.
+    #[account(mut, has_one = recovery_authority @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The recovery authority of the presale account, authorized to initiate a recovery.
+    pub recovery_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelRecovery<'info> {
+    // The presale account whose recovery will be cancelled.
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to cancel a recovery.
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteRecovery<'info> {
+    // The presale account whose ownership will be recovered.
+    #[account(mut, has_one = recovery_authority @ ErrorCode::Unauthorized)]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The recovery authority of the presale account, who becomes the new owner.
+    pub recovery_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PausePresale<'info> {
+    // The presale account that will be paused or resumed.
+    #[account(
+        mut,
+        constraint = authority.key() == presale.owner ||
+        presale.guardians.contains(&authority.key()) @ ErrorCode::Unauthorized
+    )]
+    pub presale: Account<'info, Presale>,
+
+    // The audit log recording this privileged call.
+    #[account(mut, seeds = [b"audit", presale.key().as_ref()], bump = audit_log.bump)]
+    pub audit_log: Account<'info, AuditLog>,
+
+    // The owner of the presale account, authorized to pause or resume it,
+    // or one of the guardians, authorized to pause it only.
+    pub authority: Signer<'info>,
+}
+
+// The main Presale account structure.
+#[account]
+#[derive(InitSpace)]
+pub struct Presale {
+    // The public key of the owner of the presale.
+    pub owner: Pubkey,
+
+    // The rate of tokens per SOL, in the token's smallest unit per whole SOL.
+    // It applies until total_raised reaches the first price step, if any.
+    pub rate: u128,
+
+    // The wallet for sending the SOL payments to
+    pub payment_wallet: Pubkey,
+
+    // Flag indicating whether the presale is paused.
+    pub is_paused: bool,
+
+    // The minimum amount of SOL (in lamports) accepted per purchase.
+    pub min_buy_lamports: u64,
+
+    // Flag indicating whether purchases made via CPI from other programs are rejected.
+    pub block_cpi_purchases: bool,
+
+    // The number of purchases made so far (and the sequence number of the next purchase).
+    pub purchase_counter: u64,
+
+    // The total amount of tokens for sale, in the token's smallest unit.
+    pub total_tokens_for_sale: u128,
+
+    // The amount of tokens not yet allocated to purchases.
+    pub tokens_remaining: u128,
+
+    // The destinations SOL can be withdrawn to (at most MAX_WITHDRAWAL_DESTINATIONS).
+    #[max_len(MAX_WITHDRAWAL_DESTINATIONS)]
+    pub withdrawal_allowlist: Vec<Pubkey>,
+
+    // The guardians, any of whom can pause (but not resume) the presale (at most MAX_GUARDIANS).
+    #[max_len(MAX_GUARDIANS)]
+    pub guardians: Vec<Pubkey>,
+
+    // The ID used with the initial owner to derive the presale address.
+    pub presale_id: u64,
+
+    // The bump seed of the presale address.
+    pub bump: u8,
+
+    // The time after which EVM addresses can no longer be submitted or changed (0 for none).
+    pub evm_claim_cutoff: i64,
+
+    // Flag indicating whether the first submitted EVM address is final and cannot be changed.
+    pub lock_evm_claims: bool,
+
+    // The time at which buyer contributions are snapshotted (0 for none).
+    pub snapshot_time: i64,
+
+    // Bitfield of the optional subsystems enabled for this presale (see FEATURE_*).
+    pub features: u64,
+
+    // The public key of the recovery authority, who can take over ownership after a timelock.
+    // Pubkey::default() means no recovery authority is set.
+    pub recovery_authority: Pubkey,
+
+    // The time at which the pending recovery was initiated (0 if none is in progress).
+    pub recovery_started_at: i64,
+
+    // The total amount of SOL (in lamports) paid for purchases so far.
+    pub total_raised: u64,
+
+    // The price ladder: each step replaces the rate once total_raised reaches its threshold.
+    // Thresholds are strictly increasing (at most MAX_PRICE_STEPS).
+    #[max_len(MAX_PRICE_STEPS)]
+    pub price_steps: Vec<PriceStep>,
+
+    // The number of decimals of the token being sold, which defines its smallest unit.
+    pub token_decimals: u8,
+}
+
+impl Presale {
+    // Returns whether the given feature flag is enabled for this presale.
+    pub fn has_feature(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+
+    // Changes the supply of tokens for sale, keeping the tokens already sold allocated.
+    // Any supply can be set while nothing has been sold; afterwards it can only be raised.
+    pub fn set_total_tokens_for_sale(&mut self, total_tokens_for_sale: u128) -> Result<()> {
+        require!(total_tokens_for_sale > 0, ErrorCode::ZeroAmount);
+
+        // Ensure the supply is not lowered once tokens have been sold, so allocations stay covered.
+        let tokens_sold = self.tokens_sold()?;
+        require!(
+            tokens_sold == 0 || total_tokens_for_sale >= self.total_tokens_for_sale,
+            ErrorCode::TokensForSaleDecreased
+        );
+
+        self.total_tokens_for_sale = total_tokens_for_sale;
+        self.tokens_remaining = math::checked_sub(total_tokens_for_sale, tokens_sold)?;
+
+        Ok(())
+    }
+
+    // Returns the tokens allocated to buyers so far.
+    pub fn tokens_sold(&self) -> Result<u128> {
+        math::checked_sub(self.total_tokens_for_sale, self.tokens_remaining)
+    }
+
+    // Returns the highest milestone (0, 25, 50, 75 or 100 percent of the supply sold) reached.
+    // Milestones follow the token supply, as the presale has no SOL hard cap.
+    pub fn milestone_percent(&self) -> Result<u8> {
+        let quarters = math::mul_div(self.tokens_sold()?, 4, self.total_tokens_for_sale)?;
+        Ok((quarters as u8) * 25)
+    }
+
+    // Returns whether total_raised has reached the first price step, replacing the base rate.
+    pub fn step_in_effect(&self) -> bool {
+        self.price_steps
+            .first()
+            .is_some_and(|step| self.total_raised >= step.raised_threshold)
+    }
+
+    // Checks that the price ladder thresholds are strictly increasing and that every step
+    // lowers the rate, starting from the base rate.
+    pub fn validate_price_steps(&self) -> Result<()> {
+        let mut previous_rate = self.rate;
+        let mut previous_threshold = None;
+
+        for step in &self.price_steps {
+            require!(step.rate > 0, ErrorCode::InvalidRate);
+            require!(step.rate < previous_rate, ErrorCode::PriceStepRateNotLower);
+            if let Some(previous_threshold) = previous_threshold {
+                require!(step.raised_threshold > previous_threshold, ErrorCode::InvalidPriceSteps);
+            }
+            previous_rate = step.rate;
+            previous_threshold = Some(step.raised_threshold);
+        }
+
+        Ok(())
+    }
+
+    // Returns the rate in effect once the given amount of SOL has been raised.
+    pub fn rate_at(&self, raised: u64) -> u128 {
+        self.price_steps
+            .iter()
+            .rev()
+            .find(|step| step.raised_threshold <= raised)
+            .map_or(self.rate, |step| step.rate)
+    }
+
+    // Returns the amount raised at which the rate next changes, if any.
+    fn next_threshold(&self, raised: u64) -> Option<u64> {
+        self.price_steps
+            .iter()
+            .map(|step| step.raised_threshold)
+            .find(|threshold| *threshold > raised)
+    }
+
+    // Calculates the tokens allocated for the SOL paid, starting from the current total raised.
+    // A purchase crossing a threshold is priced piecewise, each part at the rate of its step.
+    pub fn tokens_for_sol(&self, sol_amount: u64) -> Result<u128> {
+        let mut raised = self.total_raised;
+        let mut sol_left = sol_amount;
+        let mut token_amount = 0;
+
+        while sol_left > 0 {
+            // Take the part of the SOL left that is priced at the current rate.
+            let sol_part = match self.next_threshold(raised) {
+                Some(threshold) => sol_left.min(math::checked_sub(threshold, raised)?),
+                None => sol_left,
+            };
+            let tokens_part =
+                math::mul_div(sol_part.into(), self.rate_at(raised), LAMPORTS_PER_SOL.into())?;
+
+            token_amount = math::checked_add(token_amount, tokens_part)?;
+            raised = math::checked_add(raised, sol_part)?;
+            sol_left = math::checked_sub(sol_left, sol_part)?;
+        }
+
+        Ok(token_amount)
+    }
+
+    // Calculates the SOL needed to receive at least the given amount of tokens, starting from the
+    // current total raised. This is the inverse of tokens_for_sol, rounding up in the last step.
+    pub fn sol_for_tokens(&self, token_amount: u128) -> Result<u64> {
+        let mut raised = self.total_raised;
+        let mut tokens_left = token_amount;
+        let mut sol_amount = 0;
+
+        while tokens_left > 0 {
+            let rate = self.rate_at(raised);
+
+            // Buy out the rest of the current step if the tokens left do not fit in it.
+            if let Some(threshold) = self.next_threshold(raised) {
+                let sol_part = math::checked_sub(threshold, raised)?;
+                let tokens_part = math::mul_div(sol_part.into(), rate, LAMPORTS_PER_SOL.into())?;
+                if tokens_part < tokens_left {
+                    sol_amount = math::checked_add(sol_amount, sol_part)?;
+                    tokens_left = math::checked_sub(tokens_left, tokens_part)?;
+                    raised = threshold;
+                    continue;
+                }
+            }
+
+            // Otherwise, pay for the tokens left at the current rate.
+            let sol_part = math::to_u64(math::mul_div_ceil(tokens_left, LAMPORTS_PER_SOL.into(), rate)?)?;
+            sol_amount = math::checked_add(sol_amount, sol_part)?;
+            tokens_left = 0;
+        }
+
+        Ok(sol_amount)
+    }
+}
+
+// A step of the price ladder.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct PriceStep {
+    // The total amount of SOL (in lamports) raised from which this step applies.
+    pub raised_threshold: u64,
+
+    // The rate of tokens per SOL applied from the threshold on.
+    pub rate: u128,
+}
+
+// Per-buyer purchase counter, used to seed the individual purchase receipts.
+// The presale and buyer keys must stay the first fields (see RECEIPT_PRESALE_OFFSET).
+#[account]
+#[derive(InitSpace)]
+pub struct BuyerHistory {
+    // The presale this history belongs to.
+    pub presale: Pubkey,
+
+    // The buyer this history belongs to.
+    pub buyer: Pubkey,
+
+    // The number of receipted purchases made so far (and the index of the next receipt).
+    pub purchase_count: u64,
+
+    // The total amount of SOL contributed through receipted purchases.
+    pub total_contributed: u64,
+
+    // The bump seed of this account.
+    pub bump: u8,
+
+    // Flag indicating whether the snapshot of the contribution has been recorded.
+    pub snapshot_recorded: bool,
+
+    // The total amount of SOL contributed before the presale's snapshot time.
+    pub snapshot_contribution: u64,
+
+    // The total amount of tokens bought with the stake option through receipted purchases.
+    pub total_staked: u128,
+
+    // The total amount of tokens bought with the stake option before the presale's snapshot time.
+    pub snapshot_staked: u128,
+}
+
+impl BuyerHistory {
+    // Freezes the current contribution into the snapshot if the snapshot time has been reached
+    // and the snapshot has not been recorded yet. Returns whether the snapshot was recorded.
+    pub fn record_snapshot(&mut self, snapshot_time: i64, now: i64) -> bool {
+        if self.snapshot_recorded || snapshot_time == 0 || now < snapshot_time {
+            return false;
+        }
+
+        self.snapshot_contribution = self.total_contributed;
+        self.snapshot_staked = self.total_staked;
+        self.snapshot_recorded = true;
+
+        true
+    }
+}
+
+// A receipt recording the terms of a single purchase.
+// The presale and buyer keys must stay the first fields (see RECEIPT_PRESALE_OFFSET).
+#[account]
+#[derive(InitSpace)]
+pub struct PurchaseReceipt {
+    // The presale the purchase was made in.
+    pub presale: Pubkey,
+
+    // The buyer who made the purchase.
+    pub buyer: Pubkey,
+
+    // The index of this purchase within the buyer's history.
+    pub index: u64,
+
+    // The global sequence number of this purchase within the presale.
+    pub purchase_id: u64,
+
+    // The amount of SOL paid.
+    pub sol_amount: u64,
+
+    // The rate of tokens per SOL at the time of purchase.
+    pub rate: u128,
+
+    // The amount of tokens allocated for the purchase.
+    pub token_amount: u128,
+
+    // Whether the buyer opted to stake the purchased tokens.
+    pub stake: bool,
+
+    // The time at which the purchase was made.
+    pub timestamp: i64,
+
+    // The bump seed of this account.
+    pub bump: u8,
+}
+
+// A user's submitted EVM address for a presale on one chain, with the previously submitted addresses.
+#[account]
+#[derive(InitSpace)]
+pub struct EvmClaim {
+    // The presale this claim belongs to.
+    pub presale: Pubkey,
+
+    // The user who submitted the claim.
+    pub user: Pubkey,
+
+    // The EVM chain ID the tokens should be delivered on.
+    pub chain_id: u64,
+
+    // The current EVM address.
+    pub evm_address: [u8; 20],
+
+    // The time at which the current EVM address was submitted.
+    pub updated_at: i64,
+
+    // The most recently replaced EVM addresses, oldest first (at most EVM_ADDRESS_HISTORY_LEN).
+    #[max_len(EVM_ADDRESS_HISTORY_LEN)]
+    pub history: Vec<EvmAddressChange>,
+
+    // The bump seed of this account.
+    pub bump: u8,
+}
+
+// A previously submitted EVM address and the time it was replaced.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct EvmAddressChange {
+    // The replaced EVM address.
+    pub evm_address: [u8; 20],
+
+    // The time at which the address was replaced.
+    pub replaced_at: i64,
+}
+
+// The on-chain audit log of privileged calls made on a presale.
+// Entries are kept in a ring buffer and chained by hash, so tampering with history is detectable.
+#[account]
+#[derive(InitSpace)]
+pub struct AuditLog {
+    // The presale this audit log belongs to.
+    pub presale: Pubkey,
+
+    // The total number of entries ever recorded (the next entry goes to total_entries % AUDIT_LOG_CAPACITY).
+    pub total_entries: u64,
+
+    // The hash of the most recently recorded entry.
+    pub last_hash: [u8; 32],
+
+    // The most recent entries (at most AUDIT_LOG_CAPACITY).
+    #[max_len(AUDIT_LOG_CAPACITY)]
+    pub entries: Vec<AuditEntry>,
+
+    // The bump seed of this account.
+    pub bump: u8,
+}
+
+impl AuditLog {
+    // Records a privileged call, overwriting the oldest entry once the buffer is full.
+    pub fn record(
+        &mut self,
+        action: AuditAction,
+        authority: Pubkey,
+        param_key: Pubkey,
+        param_value: u128
+    ) -> Result<()> {
+        let slot = Clock::get()?.slot;
+
+        // Chain the entry to the previous one.
+        let hash = hashv(
+            &[
+                &self.last_hash,
+                &[action as u8],
+                authority.as_ref(),
+                param_key.as_ref(),
+                &param_value.to_le_bytes(),
+                &slot.to_le_bytes(),
+            ]
+        ).to_bytes();
+
+        let entry = AuditEntry {
+            action,
+            authority,
+            param_key,
+            param_value,
+            slot,
+            hash,
+        };
+
+        // Append the entry, or overwrite the oldest one once the buffer is full.
+        let position = (self.total_entries % (AUDIT_LOG_CAPACITY as u64)) as usize;
+        if position < self.entries.len() {
+            self.entries[position] = entry;
+        } else {
+            self.entries.push(entry);
+        }
+
+        self.total_entries = math::checked_add(self.total_entries, 1)?;
+        self.last_hash = hash;
+
+        Ok(())
+    }
+}
+
+// A single privileged call recorded in the audit log.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct AuditEntry {
+    // The privileged action that was performed.
+    pub action: AuditAction,
+
+    // The account that performed the action.
+    pub authority: Pubkey,
+
+    // The key parameter of the action (e.g. the new wallet), or Pubkey::default() if none.
+    pub param_key: Pubkey,
+
+    // The numeric parameter of the action (e.g. the new rate or amount), or 0 if none.
+    pub param_value: u128,
+
+    // The slot in which the action was performed.
+    pub slot: u64,
+
+    // The hash of this entry chained with the previous entry's hash.
+    pub hash: [u8; 32],
+}
+
+// The privileged actions recorded in the audit log.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum AuditAction {
+    Initialize,
+    WithdrawSol,
+    ChangeRate,
+    ChangeMinBuy,
+    ChangePaymentWallet,
+    AddGuardian,
+    RemoveGuardian,
+    ChangeCpiGuard,
+    ChangeEvmClaimCutoff,
+    ChangeEvmClaimLock,
+    ChangeSnapshotTime,
+    PausePresale,
+    RenounceOwnership,
+    ChangeRecoveryAuthority,
+    InitiateRecovery,
+    CancelRecovery,
+    CompleteRecovery,
+    ChangePriceSteps,
+    ChangeTokensForSale,
+    MigratePresale,
+}
+
+// The initial configuration of a presale, passed to initialize.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct InitializeParams {
+    // The wallet for sending the SOL payments to.
+    pub payment_wallet: Pubkey,
+
+    // The rate of tokens per SOL, in the token's smallest unit per whole SOL.
+    pub rate: u128,
+
+    // The minimum amount of SOL (in lamports) accepted per purchase.
+    pub min_buy_lamports: u64,
+
+    // The destinations SOL can be withdrawn to. Empty defaults to the payment wallet.
+    pub withdrawal_allowlist: Vec<Pubkey>,
+
+    // Bitfield of the optional subsystems to enable (see FEATURE_*).
+    pub features: u64,
+
+    // The total amount of tokens for sale, in the token's smallest unit.
+    pub total_tokens_for_sale: u128,
+
+    // The number of decimals of the token being sold (at most MAX_TOKEN_DECIMALS).
+    pub token_decimals: u8,
+}
+
+// A set of configuration changes applied by update_config and initialize_from.
+// Fields left as None are unchanged.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ConfigUpdate {
+    // The new rate of tokens per SOL.
+    pub rate: Option<u128>,
+
+    // The new wallet for sending the SOL payments to.
+    pub payment_wallet: Option<Pubkey>,
+
+    // The new minimum amount of SOL (in lamports) accepted per purchase.
+    pub min_buy_lamports: Option<u64>,
+
+    // The new paused state of the presale.
+    pub is_paused: Option<bool>,
+
+    // Whether purchases made via CPI from other programs are rejected.
+    pub block_cpi_purchases: Option<bool>,
+
+    // The new time after which EVM addresses can no longer be submitted or changed (0 for none).
+    pub evm_claim_cutoff: Option<i64>,
+
+    // Whether the first submitted EVM address is final.
+    pub lock_evm_claims: Option<bool>,
+
+    // The new time at which buyer contributions are snapshotted (0 for none).
+    pub snapshot_time: Option<i64>,
+
+    // The new price ladder (an empty list removes it).
+    pub price_steps: Option<Vec<PriceStep>>,
+
+    // The new total amount of tokens for sale. Once tokens are sold it can only be raised.
+    pub total_tokens_for_sale: Option<u128>,
+}
+
+// The overrides applied by initialize_from on top of the configuration copied from the source.
+// Fields left as None keep the source's value.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct InitializeFromOverrides {
+    // Changes to the settings that can also be changed later with update_config.
+    pub config: ConfigUpdate,
+
+    // The destinations SOL can be withdrawn to. Empty defaults to the payment wallet.
+    pub withdrawal_allowlist: Option<Vec<Pubkey>>,
+
+    // Bitfield of the optional subsystems to enable (see FEATURE_*).
+    pub features: Option<u64>,
+
+    // The number of decimals of the token being sold (at most MAX_TOKEN_DECIMALS).
+    pub token_decimals: Option<u8>,
+}
+
+// The result of a purchase, returned by the buy instructions (as return data) for composability.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PurchaseResult {
+    // The global sequence number of the purchase.
+    pub purchase_id: u64,
+
+    // The amount of SOL paid.
+    pub sol_amount: u64,
+
+    // The rate of tokens per SOL in effect when the purchase started.
+    // A purchase crossing a price step is partly priced at the next rate.
+    pub rate: u128,
+
+    // The amount of tokens (in the token's smallest unit) allocated for the purchase.
+    pub token_amount: u128,
+
+    // The number of decimals of the token, needed to convert token_amount to whole tokens.
+    pub token_decimals: u8,
+}
+
+// Custom error codes used in the program.
+#[error_code]
+pub enum ErrorCode {
+    // Indicates that the presale is currently paused.
+    #[msg("The presale is currently paused.")]
+    PresaleIsPaused,
+
+    // Indicates an overflow error, likely during token allocation calculation.
+    #[msg("Operation overflowed.")]
+    Overflow,
+
+    // Indicates an underflow error, likely during token allocation calculation.
+    #[msg("Operation underflowed.")]
+    Underflow,
+
+    // Indicates an unauthorized attempt to perform an operation.
     #[msg("Unauthorized.")]
     Unauthorized,
 
@@ -316,6 +2262,170 @@ pub enum ErrorCode {
     // Indicates that the amount of SOL transferred does not match the expected amount.
     #[msg("Invalid amount of SOL transferred.")]
     InvalidAmountTransferred,
+
+    // Indicates that the purchase amount is below the configured minimum.
+    #[msg("Purchase amount is below the minimum.")]
+    BelowMinimumPurchase,
+
+    // Indicates that a rate of zero tokens per SOL was provided.
+    #[msg("The rate must be greater than zero.")]
+    InvalidRate,
+
+    // Indicates that an amount of zero was provided.
+    #[msg("The amount must be greater than zero.")]
+    ZeroAmount,
+
+    // Indicates that an empty EVM address was provided.
+    #[msg("The EVM address must not be empty.")]
+    EmptyEvmAddress,
+
+    // Indicates that a purchase was attempted via CPI while the CPI guard is enabled.
+    #[msg("Purchases via CPI are not allowed.")]
+    CpiPurchaseNotAllowed,
+
+    // Indicates that more withdrawal destinations were provided than the presale can store.
+    #[msg("Too many withdrawal destinations provided.")]
+    WithdrawalAllowlistTooLong,
+
+    // Indicates that the withdrawal recipient is not in the withdrawal allowlist.
+    #[msg("The recipient is not an allowed withdrawal destination.")]
+    RecipientNotAllowed,
+
+    // Indicates that the purchase memo exceeds MAX_MEMO_LEN.
+    #[msg("The memo is too long.")]
+    MemoTooLong,
+
+    // Indicates that a guardian attempted to resume the presale.
+    #[msg("The guardian can only pause the presale.")]
+    GuardianCannotUnpause,
+
+    // Indicates that the EVM claim cutoff has passed.
+    #[msg("EVM addresses can no longer be submitted or changed.")]
+    EvmClaimClosed,
+
+    // Indicates that the EVM address was already submitted and the presale locks EVM claims.
+    #[msg("The EVM address has already been submitted and cannot be changed.")]
+    EvmAddressLocked,
+
+    // Indicates that the EVM address is not 0x followed by 40 hex digits.
+    #[msg("The EVM address is not a valid 0x-prefixed 20 byte hex address.")]
+    InvalidEvmAddress,
+
+    // Indicates that a mixed case EVM address does not match its EIP-55 checksum.
+    #[msg("The EVM address checksum is invalid.")]
+    InvalidEvmAddressChecksum,
+
+    // Indicates that no snapshot time is configured or it has not been reached yet.
+    #[msg("The snapshot time has not been reached.")]
+    SnapshotNotReached,
+
+    // Indicates that the buyer's snapshot has already been recorded.
+    #[msg("The snapshot has already been recorded.")]
+    SnapshotAlreadyRecorded,
+
+    // Indicates that unknown feature flags were provided.
+    #[msg("Unknown feature flags provided.")]
+    InvalidFeatures,
+
+    // Indicates that the instruction belongs to a feature not enabled for this presale.
+    #[msg("This feature is not enabled for the presale.")]
+    FeatureDisabled,
+
+    // Indicates that a recovery is already in progress.
+    #[msg("A recovery is already in progress.")]
+    RecoveryAlreadyInitiated,
+
+    // Indicates that no recovery is in progress.
+    #[msg("No recovery is in progress.")]
+    NoRecoveryInProgress,
+
+    // Indicates that the recovery timelock has not passed yet.
+    #[msg("The recovery timelock has not passed yet.")]
+    RecoveryTimelockActive,
+
+    // Indicates that the purchase would allocate more tokens than remain for sale.
+    #[msg("Not enough tokens remaining for this purchase.")]
+    InsufficientTokensRemaining,
+
+    // Indicates that the guardian set is already full.
+    #[msg("Too many guardians.")]
+    TooManyGuardians,
+
+    // Indicates that the guardian is already in the set.
+    #[msg("The guardian has already been added.")]
+    GuardianAlreadyAdded,
+
+    // Indicates that the guardian is not in the set.
+    #[msg("The guardian was not found.")]
+    GuardianNotFound,
+
+    // Indicates that a chain ID of zero was provided.
+    #[msg("The chain ID must be greater than zero.")]
+    InvalidChainId,
+
+    // Indicates that the SOL required for the requested tokens exceeds the buyer's maximum.
+    #[msg("The required SOL exceeds the maximum provided.")]
+    MaxSolExceeded,
+
+    // Indicates that the payment wallet is not owned by the system program.
+    #[msg("The payment wallet must be owned by the system program.")]
+    PaymentWalletNotSystemOwned,
+
+    // Indicates that the payment wallet is an executable account.
+    #[msg("The payment wallet must not be executable.")]
+    PaymentWalletExecutable,
+
+    // Indicates that the payment wallet does not hold enough lamports to be rent-exempt.
+    #[msg("The payment wallet must be rent-exempt.")]
+    PaymentWalletNotRentExempt,
+
+    // Indicates that a payment reference account was passed as writable.
+    #[msg("Payment reference accounts must be read-only.")]
+    InvalidPaymentReference,
+
+    // Indicates that the price ladder has more steps than fit in the presale account.
+    #[msg("Too many price steps.")]
+    TooManyPriceSteps,
+
+    // Indicates that the price ladder thresholds are not strictly increasing.
+    #[msg("Price step thresholds must be strictly increasing.")]
+    InvalidPriceSteps,
+
+    // Indicates that the token has more decimals than supported.
+    #[msg("The token decimals exceed the maximum supported.")]
+    InvalidTokenDecimals,
+
+    // Indicates an attempt to lower the supply of tokens for sale after tokens were sold.
+    #[msg("The tokens for sale can only be raised once tokens have been sold.")]
+    TokensForSaleDecreased,
+
+    // Indicates that a price step does not lower the rate of the step before it.
+    #[msg("Each price step must have a lower rate than the one before it.")]
+    PriceStepRateNotLower,
+
+    // Indicates an attempt to change the base rate after a price step has replaced it.
+    #[msg("The base rate can no longer be changed once a price step applies.")]
+    RateLockedByPriceStep,
+
+    // Indicates an attempt to change the snapshot time after it has been reached.
+    #[msg("The snapshot time can no longer be changed once it has been reached.")]
+    SnapshotTimeReached,
+
+    // Indicates an attempt to set the snapshot time to a time that has already passed.
+    #[msg("The snapshot time must be in the future.")]
+    SnapshotTimeInPast,
+
+    // Indicates a purchase without a receipt on a presale that records contributions in receipts.
+    #[msg("Purchases on this presale must be made with a receipt.")]
+    ReceiptRequired,
+
+    // Indicates that the account is not a presale in the version 6 layout.
+    #[msg("The account is not a presale in the legacy layout.")]
+    NotLegacyPresale,
+
+    // Indicates a change of token decimals without restating the amounts expressed in the token's unit.
+    #[msg("Changing the token decimals requires overriding the rate, price steps and tokens for sale.")]
+    TokenAmountsNotOverridden,
 }
 
 security_txt! {
@@ -330,3 +2440,253 @@ security_txt! {
     source_code: "https://github.com/teamaquadoge/presale-solana",
     acknowledgements: "Thanks for finding a bug in our program! Please report it to team@aquadoge.com"
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One whole token with 18 decimals.
+    const TOKEN: u128 = 1_000_000_000_000_000_000;
+
+    fn presale_with_ladder(rate: u128, price_steps: Vec<PriceStep>, total_raised: u64) -> Presale {
+        Presale {
+            owner: Pubkey::default(),
+            rate,
+            payment_wallet: Pubkey::default(),
+            is_paused: false,
+            min_buy_lamports: 0,
+            block_cpi_purchases: false,
+            purchase_counter: 0,
+            total_tokens_for_sale: u128::MAX,
+            tokens_remaining: u128::MAX,
+            withdrawal_allowlist: vec![],
+            guardians: vec![],
+            presale_id: 0,
+            bump: 0,
+            evm_claim_cutoff: 0,
+            lock_evm_claims: false,
+            snapshot_time: 0,
+            features: 0,
+            recovery_authority: Pubkey::default(),
+            recovery_started_at: 0,
+            total_raised,
+            price_steps,
+            token_decimals: 18,
+        }
+    }
+
+    // 1000 tokens per SOL, then 800 from 500 SOL raised and 500 from 1000 SOL raised.
+    fn ladder(total_raised: u64) -> Presale {
+        presale_with_ladder(
+            1_000 * TOKEN,
+            vec![
+                PriceStep { raised_threshold: 500 * LAMPORTS_PER_SOL, rate: 800 * TOKEN },
+                PriceStep { raised_threshold: 1_000 * LAMPORTS_PER_SOL, rate: 500 * TOKEN },
+            ],
+            total_raised
+        )
+    }
+
+    #[test]
+    fn prices_a_purchase_within_a_step_at_its_rate() {
+        assert_eq!(ladder(0).tokens_for_sol(2 * LAMPORTS_PER_SOL).unwrap(), 2_000 * TOKEN);
+        let presale = ladder(600 * LAMPORTS_PER_SOL);
+        assert_eq!(presale.tokens_for_sol(LAMPORTS_PER_SOL).unwrap(), 800 * TOKEN);
+    }
+
+    #[test]
+    fn prices_a_purchase_straddling_a_threshold_piecewise() {
+        // 1 SOL at 1000 tokens, then 1 SOL at 800 tokens.
+        let presale = ladder(499 * LAMPORTS_PER_SOL);
+        assert_eq!(presale.tokens_for_sol(2 * LAMPORTS_PER_SOL).unwrap(), 1_800 * TOKEN);
+
+        // 1 SOL at 1000 tokens, 500 SOL at 800 tokens, then 1 SOL at 500 tokens.
+        assert_eq!(presale.tokens_for_sol(502 * LAMPORTS_PER_SOL).unwrap(), 401_500 * TOKEN);
+    }
+
+    #[test]
+    fn quotes_the_sol_for_tokens_across_a_threshold() {
+        let presale = ladder(499 * LAMPORTS_PER_SOL);
+        assert_eq!(presale.sol_for_tokens(1_800 * TOKEN).unwrap(), 2 * LAMPORTS_PER_SOL);
+    }
+
+    #[test]
+    fn sol_for_tokens_buys_at_least_the_requested_tokens() {
+        // Uneven rates so that the quotes need rounding.
+        let presale = presale_with_ladder(
+            7 * TOKEN / 3,
+            vec![
+                PriceStep { raised_threshold: 3 * LAMPORTS_PER_SOL, rate: 5 * TOKEN / 7 },
+                PriceStep { raised_threshold: 5 * LAMPORTS_PER_SOL + 1, rate: 3 },
+            ],
+            LAMPORTS_PER_SOL + 17
+        );
+
+        for token_amount in [1, 2, 999, TOKEN, 3 * TOKEN + 1, 5 * TOKEN, 7 * TOKEN / 3 + 11] {
+            let sol_amount = presale.sol_for_tokens(token_amount).unwrap();
+            assert!(presale.tokens_for_sol(sol_amount).unwrap() >= token_amount);
+
+            // One lamport less is not enough, so the quote is not more than needed.
+            assert!(presale.tokens_for_sol(sol_amount - 1).unwrap() < token_amount);
+        }
+    }
+
+    #[test]
+    fn rate_at_follows_the_ladder() {
+        let presale = ladder(0);
+        assert_eq!(presale.rate_at(0), 1_000 * TOKEN);
+        assert_eq!(presale.rate_at(500 * LAMPORTS_PER_SOL - 1), 1_000 * TOKEN);
+        assert_eq!(presale.rate_at(500 * LAMPORTS_PER_SOL), 800 * TOKEN);
+        assert_eq!(presale.rate_at(u64::MAX), 500 * TOKEN);
+
+        assert!(!ladder(500 * LAMPORTS_PER_SOL - 1).step_in_effect());
+        assert!(ladder(500 * LAMPORTS_PER_SOL).step_in_effect());
+    }
+
+    #[test]
+    fn validates_that_each_step_lowers_the_rate() {
+        assert!(ladder(0).validate_price_steps().is_ok());
+
+        // The first step must be below the base rate.
+        let mut presale = ladder(0);
+        presale.rate = 800 * TOKEN;
+        let err = presale.validate_price_steps().unwrap_err();
+        assert_eq!(err, ErrorCode::PriceStepRateNotLower.into());
+
+        // Later steps must be below the step before them.
+        let mut presale = ladder(0);
+        presale.price_steps[1].rate = 800 * TOKEN;
+        let err = presale.validate_price_steps().unwrap_err();
+        assert_eq!(err, ErrorCode::PriceStepRateNotLower.into());
+
+        // Thresholds must be strictly increasing.
+        let mut presale = ladder(0);
+        presale.price_steps[1].raised_threshold = 500 * LAMPORTS_PER_SOL;
+        let err = presale.validate_price_steps().unwrap_err();
+        assert_eq!(err, ErrorCode::InvalidPriceSteps.into());
+    }
+
+    fn buyer_history(total_contributed: u64, total_staked: u128) -> BuyerHistory {
+        BuyerHistory {
+            presale: Pubkey::default(),
+            buyer: Pubkey::default(),
+            purchase_count: 1,
+            total_contributed,
+            bump: 0,
+            snapshot_recorded: false,
+            snapshot_contribution: 0,
+            total_staked,
+            snapshot_staked: 0,
+        }
+    }
+
+    #[test]
+    fn records_the_snapshot_once_the_time_is_reached() {
+        let mut history = buyer_history(5 * LAMPORTS_PER_SOL, 3 * TOKEN);
+
+        // Nothing is recorded before the snapshot time, or without one.
+        assert!(!history.record_snapshot(100, 99));
+        assert!(!history.record_snapshot(0, 1_000));
+        assert!(!history.snapshot_recorded);
+
+        assert!(history.record_snapshot(100, 100));
+        assert_eq!(history.snapshot_contribution, 5 * LAMPORTS_PER_SOL);
+        assert_eq!(history.snapshot_staked, 3 * TOKEN);
+    }
+
+    #[test]
+    fn keeps_the_recorded_snapshot_frozen() {
+        let mut history = buyer_history(5 * LAMPORTS_PER_SOL, 3 * TOKEN);
+        assert!(history.record_snapshot(100, 150));
+
+        // Later purchases do not change the snapshot.
+        history.total_contributed += LAMPORTS_PER_SOL;
+        history.total_staked += TOKEN;
+        assert!(!history.record_snapshot(100, 200));
+        assert_eq!(history.snapshot_contribution, 5 * LAMPORTS_PER_SOL);
+        assert_eq!(history.snapshot_staked, 3 * TOKEN);
+    }
+
+    #[test]
+    fn allows_only_future_snapshot_times_before_the_current_one_is_reached() {
+        // Setting, moving or clearing a snapshot time that has not been reached.
+        assert!(check_snapshot_time_change(0, 200, 100).is_ok());
+        assert!(check_snapshot_time_change(150, 300, 100).is_ok());
+        assert!(check_snapshot_time_change(150, 0, 100).is_ok());
+
+        // A time in the past would count purchases made after it.
+        let err = check_snapshot_time_change(0, 100, 100).unwrap_err();
+        assert_eq!(err, ErrorCode::SnapshotTimeInPast.into());
+        let err = check_snapshot_time_change(150, 50, 100).unwrap_err();
+        assert_eq!(err, ErrorCode::SnapshotTimeInPast.into());
+
+        // Snapshots may already be recorded once the current time is reached.
+        let err = check_snapshot_time_change(100, 300, 100).unwrap_err();
+        assert_eq!(err, ErrorCode::SnapshotTimeReached.into());
+    }
+
+    #[test]
+    fn sets_any_supply_while_nothing_is_sold() {
+        let mut presale = ladder(0);
+        presale.total_tokens_for_sale = 1_000 * TOKEN;
+        presale.tokens_remaining = 1_000 * TOKEN;
+
+        presale.set_total_tokens_for_sale(400 * TOKEN).unwrap();
+        assert_eq!(presale.total_tokens_for_sale, 400 * TOKEN);
+        assert_eq!(presale.tokens_remaining, 400 * TOKEN);
+
+        let err = presale.set_total_tokens_for_sale(0).unwrap_err();
+        assert_eq!(err, ErrorCode::ZeroAmount.into());
+    }
+
+    #[test]
+    fn only_raises_the_supply_once_tokens_are_sold() {
+        let mut presale = ladder(0);
+        presale.total_tokens_for_sale = 1_000 * TOKEN;
+        presale.tokens_remaining = 700 * TOKEN;
+
+        // The 300 tokens sold stay allocated.
+        presale.set_total_tokens_for_sale(1_500 * TOKEN).unwrap();
+        assert_eq!(presale.tokens_remaining, 1_200 * TOKEN);
+
+        let err = presale.set_total_tokens_for_sale(1_400 * TOKEN).unwrap_err();
+        assert_eq!(err, ErrorCode::TokensForSaleDecreased.into());
+        assert_eq!(presale.total_tokens_for_sale, 1_500 * TOKEN);
+    }
+
+    #[test]
+    fn reports_the_highest_milestone_reached() {
+        let mut presale = ladder(0);
+        presale.total_tokens_for_sale = 1_000 * TOKEN;
+
+        for (tokens_remaining, milestone) in [
+            (1_000 * TOKEN, 0),
+            (750 * TOKEN + 1, 0),
+            (750 * TOKEN, 25),
+            (300 * TOKEN, 50),
+            (1, 75),
+            (0, 100),
+        ] {
+            presale.tokens_remaining = tokens_remaining;
+            assert_eq!(presale.milestone_percent().unwrap(), milestone);
+        }
+    }
+
+    #[test]
+    fn parses_a_legacy_presale() {
+        let owner = Pubkey::new_unique();
+        let mut data = vec![0u8; LEGACY_PRESALE_SPACE];
+        data[..8].copy_from_slice(&Presale::DISCRIMINATOR);
+        data[8..40].copy_from_slice(owner.as_ref());
+        data[40..48].copy_from_slice(&1_000u64.to_le_bytes());
+        data[80] = 1;
+        assert_eq!(parse_legacy_presale(&data).unwrap(), (owner, true));
+
+        // Accounts of another type or size are rejected.
+        let err = parse_legacy_presale(&data[..LEGACY_PRESALE_SPACE - 1]).unwrap_err();
+        assert_eq!(err, ErrorCode::NotLegacyPresale.into());
+        data[0] ^= 1;
+        let err = parse_legacy_presale(&data).unwrap_err();
+        assert_eq!(err, anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch.into());
+    }
+}
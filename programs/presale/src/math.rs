@@ -0,0 +1,101 @@
+// Checked arithmetic helpers shared across the program.
+// Every helper returns ErrorCode::Overflow or ErrorCode::Underflow instead of wrapping or panicking.
+// SOL amounts are u64 lamports. Token amounts are u128, since 18-decimal supplies overflow a u64.
+
+use anchor_lang::prelude::*;
+
+use crate::ErrorCode;
+
+// The unsigned integer types the checked helpers work on.
+pub trait CheckedInteger: Copy {
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_sub(self, other: Self) -> Option<Self>;
+}
+
+impl CheckedInteger for u64 {
+    fn checked_add(self, other: Self) -> Option<Self> {
+        u64::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        u64::checked_sub(self, other)
+    }
+}
+
+impl CheckedInteger for u128 {
+    fn checked_add(self, other: Self) -> Option<Self> {
+        u128::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        u128::checked_sub(self, other)
+    }
+}
+
+// Adds two amounts, failing on overflow.
+pub fn checked_add<T: CheckedInteger>(a: T, b: T) -> Result<T> {
+    CheckedInteger::checked_add(a, b).ok_or_else(|| error!(ErrorCode::Overflow))
+}
+
+// Subtracts b from a, failing on underflow.
+pub fn checked_sub<T: CheckedInteger>(a: T, b: T) -> Result<T> {
+    CheckedInteger::checked_sub(a, b).ok_or_else(|| error!(ErrorCode::Underflow))
+}
+
+// Computes a * b / denominator (rounded down).
+pub fn mul_div(a: u128, b: u128, denominator: u128) -> Result<u128> {
+    a.checked_mul(b)
+        .and_then(|product| product.checked_div(denominator))
+        .ok_or_else(|| error!(ErrorCode::Overflow))
+}
+
+// Computes a * b / denominator (rounded up).
+pub fn mul_div_ceil(a: u128, b: u128, denominator: u128) -> Result<u128> {
+    a.checked_mul(b)
+        .and_then(|product| product.checked_add(denominator.checked_sub(1)?))
+        .and_then(|product| product.checked_div(denominator))
+        .ok_or_else(|| error!(ErrorCode::Overflow))
+}
+
+// Narrows a 128-bit result to a u64 amount, failing if it does not fit.
+pub fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| error!(ErrorCode::Overflow))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_rounds_down() {
+        assert_eq!(mul_div(7, 3, 2).unwrap(), 10);
+        assert_eq!(mul_div(6, 3, 2).unwrap(), 9);
+    }
+
+    #[test]
+    fn mul_div_ceil_rounds_up_only_when_inexact() {
+        assert_eq!(mul_div_ceil(7, 3, 2).unwrap(), 11);
+        assert_eq!(mul_div_ceil(6, 3, 2).unwrap(), 9);
+        assert_eq!(mul_div_ceil(0, 3, 2).unwrap(), 0);
+    }
+
+    #[test]
+    fn mul_div_fails_on_overflow_and_zero_denominator() {
+        assert_eq!(mul_div(u128::MAX, 2, 1).unwrap_err(), ErrorCode::Overflow.into());
+        assert_eq!(mul_div(1, 1, 0).unwrap_err(), ErrorCode::Overflow.into());
+        assert_eq!(mul_div_ceil(1, 1, 0).unwrap_err(), ErrorCode::Overflow.into());
+    }
+
+    #[test]
+    fn checked_helpers_fail_instead_of_wrapping() {
+        assert_eq!(checked_add(u64::MAX, 1).unwrap_err(), ErrorCode::Overflow.into());
+        assert_eq!(checked_sub(0u128, 1).unwrap_err(), ErrorCode::Underflow.into());
+        assert_eq!(checked_add(1u128, 2).unwrap(), 3);
+    }
+
+    #[test]
+    fn to_u64_fails_when_the_value_does_not_fit() {
+        assert_eq!(to_u64(u64::MAX as u128).unwrap(), u64::MAX);
+        assert_eq!(to_u64(u64::MAX as u128 + 1).unwrap_err(), ErrorCode::Overflow.into());
+    }
+}